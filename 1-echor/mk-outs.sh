@@ -7,3 +7,11 @@ echo "Hello there" > $OUTDIR/hello1.txt
 echo "Hello"  "there" > $OUTDIR/hello2.txt
 echo -n "Hello  there" > $OUTDIR/hello1.n.txt
 echo -n "Hello"  "there" > $OUTDIR/hello2.n.txt
+echo -e "Hello\tthere\\\\\nworld" > $OUTDIR/escapes.e.txt
+echo -E "Hello\tthere\\\\\nworld" > $OUTDIR/escapes.E.txt
+echo -e "\0101\x42\u263A\a" > $OUTDIR/codes.e.txt
+echo -e "Hello\cthere" > $OUTDIR/stop.e.txt
+printf "%-6s|%5.2f|%04x\n" apple 3.14159 255 banana 2 4096 > $OUTDIR/format.txt
+printf "%s %b\n" tab 'a\tb' > $OUTDIR/format.b.txt
+printf "Hello\0my friend\0" > $OUTDIR/hello.z.txt
+printf "a, b, c;" > $OUTDIR/abc.sep.txt
@@ -0,0 +1,866 @@
+use crate::Count::*;
+use crate::Piece::*;
+use clap::{App, Arg};
+use std::error::Error;
+use std::io::Write;
+
+type MyResult<T> = Result<T, Box<dyn Error>>;
+
+#[derive(Debug)]
+pub struct Config {
+    text: Vec<String>,
+    omit_newline: bool,
+    escapes: bool,
+    separator: String,
+    terminator: String,
+    format: Option<String>,
+}
+
+// printf格式串解析后的片段
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Literal(Vec<u8>),
+    Spec(Spec),
+    // 格式串里的\c
+    Stop,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Count {
+    Fixed(usize),
+    // `*`，从参数里取
+    FromArg,
+}
+
+// 宽度和精度的上限。coreutils是INT_MAX，不过这里整个输出先攒在内存里，
+// 太大的宽度会直接把内存撑爆，所以卡得紧一点
+const MAX_COUNT: usize = 1 << 20;
+
+#[derive(Debug, PartialEq, Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alt: bool,
+    width: Option<Count>,
+    precision: Option<Count>,
+    conversion: char,
+}
+
+pub fn get_args() -> MyResult<Config> {
+    let matches = App::new("echor")
+        .version("0.1.0")
+        .author("Ken Youens-Clark <kyclark@gmail.com>")
+        .about("Rust echo")
+        .arg(
+            // Create a new Arg with the name text. This is a required positional argument that must appear at least once and can be repeated.这种arg，positional argument 至少得有一个, 因为那个required true吧
+            Arg::with_name("text")
+                .value_name("TEXT")
+                .help("Input text")
+                // printf模式下可以一个参数都没有
+                .required_unless("format")
+                .min_values(1),
+        )
+        .arg(
+            Arg::with_name("omit_newline")
+                .short("n")
+                .help("Do not print newline")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("escapes")
+                .short("e")
+                .help("Enable interpretation of backslash escapes")
+                .takes_value(false)
+                // -e 和 -E 谁在后面谁生效，和GNU echo一样
+                .overrides_with("no_escapes"),
+        )
+        .arg(
+            Arg::with_name("no_escapes")
+                .short("E")
+                .help("Disable interpretation of backslash escapes (default)")
+                .takes_value(false)
+                .overrides_with("escapes"),
+        )
+        .arg(
+            Arg::with_name("separator")
+                .short("s")
+                .long("separator")
+                .value_name("STR")
+                .help("Separate TEXT with STR instead of a space")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("terminator")
+                .short("t")
+                .long("terminator")
+                .value_name("STR")
+                .help("End the output with STR instead of a newline")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("zero")
+                .short("z")
+                .long("zero")
+                .help("End each TEXT with NUL, for xargs -0")
+                .takes_value(false)
+                .conflicts_with_all(&["separator", "terminator"]),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Format TEXT like printf, reusing FORMAT until all TEXT is consumed")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "omit_newline",
+                    "escapes",
+                    "no_escapes",
+                    "separator",
+                    "terminator",
+                    "zero",
+                ]),
+        )
+        .get_matches();
+
+    // -z 就是分隔符和结尾都换成NUL
+    let zero = matches.is_present("zero");
+    let separator = matches
+        .value_of("separator")
+        .unwrap_or(if zero { "\0" } else { " " });
+    let terminator = matches
+        .value_of("terminator")
+        .unwrap_or(if zero { "\0" } else { "\n" });
+
+    Ok(Config {
+        text: matches.values_of_lossy("text").unwrap_or_default(),
+        omit_newline: matches.is_present("omit_newline"),
+        escapes: matches.is_present("escapes"),
+        separator: separator.to_string(),
+        terminator: terminator.to_string(),
+        format: matches.value_of("format").map(String::from),
+    })
+}
+
+// 输出写到传进来的Write里，main里给stdout，测试里给Vec<u8>
+pub fn run(config: Config, mut out: impl Write) -> MyResult<()> {
+    let output = match &config.format {
+        Some(format) => printf(format, &config.text)?,
+        None => echo(&config),
+    };
+
+    out.write_all(&output)?;
+    out.flush()?;
+    Ok(())
+}
+
+// --------------------------------------------------
+// The separator and terminator are written as given; -e only applies to TEXT.
+fn echo(config: &Config) -> Vec<u8> {
+    let mut output = vec![];
+    for (i, text) in config.text.iter().enumerate() {
+        if i > 0 {
+            output.extend_from_slice(config.separator.as_bytes());
+        }
+        if config.escapes {
+            let (bytes, stopped) = unescape(text);
+            output.extend(bytes);
+            // \c 会连后面的参数和结尾一起吃掉
+            if stopped {
+                return output;
+            }
+        } else {
+            output.extend_from_slice(text.as_bytes());
+        }
+    }
+
+    if !config.omit_newline {
+        output.extend_from_slice(config.terminator.as_bytes());
+    }
+    output
+}
+
+// --------------------------------------------------
+// Expand `format` over `args` the way POSIX printf does: the format is reused
+// until every argument has been consumed, and missing arguments read as empty.
+fn printf(format: &str, args: &[String]) -> MyResult<Vec<u8>> {
+    let pieces = parse_format(format)?;
+    let mut out = vec![];
+    let mut used = 0;
+
+    loop {
+        let start = used;
+        for piece in &pieces {
+            match piece {
+                Literal(bytes) => out.extend_from_slice(bytes),
+                Stop => return Ok(out),
+                Spec(spec) => {
+                    if format_spec(spec, args, &mut used, &mut out)? {
+                        return Ok(out);
+                    }
+                }
+            }
+        }
+        // 格式串没吃参数，或者参数用完了就停
+        if used == start || used >= args.len() {
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+// --------------------------------------------------
+fn parse_format(format: &str) -> MyResult<Vec<Piece>> {
+    let bytes = format.as_bytes();
+    let mut pieces = vec![];
+    let mut literal = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if i + 1 < bytes.len() => {
+                match push_escape(&bytes[i + 1..], true, &mut literal) {
+                    Some(len) => i += len + 1,
+                    None => {
+                        pieces.push(Literal(std::mem::take(&mut literal)));
+                        pieces.push(Stop);
+                        return Ok(pieces);
+                    }
+                }
+            }
+            b'%' if bytes.get(i + 1) == Some(&b'%') => {
+                literal.push(b'%');
+                i += 2;
+            }
+            b'%' => {
+                let (spec, len) = parse_spec(&format[i..])?;
+                if !literal.is_empty() {
+                    pieces.push(Literal(std::mem::take(&mut literal)));
+                }
+                pieces.push(Spec(spec));
+                i += len;
+            }
+            b => {
+                literal.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    if !literal.is_empty() {
+        pieces.push(Literal(literal));
+    }
+    Ok(pieces)
+}
+
+// --------------------------------------------------
+// Parse one `%[flags][width][.precision]conversion` directive, returning it and
+// its length in bytes.
+fn parse_spec(text: &str) -> MyResult<(Spec, usize)> {
+    let bytes = text.as_bytes();
+    let mut spec = Spec::default();
+    let mut i = 1;
+
+    while let Some(&b) = bytes.get(i) {
+        match b {
+            b'-' => spec.left = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'0' => spec.zero = true,
+            b'#' => spec.alt = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let count = |i: &mut usize| -> Option<Count> {
+        if bytes.get(*i) == Some(&b'*') {
+            *i += 1;
+            return Some(FromArg);
+        }
+        let (val, len) = parse_digits(&bytes[*i..], 10, usize::MAX);
+        *i += len;
+        (len > 0).then_some(Fixed(val as usize))
+    };
+
+    spec.width = count(&mut i);
+    if let Some(Fixed(n)) = spec.width {
+        check_count(n, "field width", &text[..i])?;
+    }
+    if bytes.get(i) == Some(&b'.') {
+        i += 1;
+        // 只有一个点的精度就是0
+        spec.precision = Some(count(&mut i).unwrap_or(Fixed(0)));
+        if let Some(Fixed(n)) = spec.precision {
+            check_count(n, "precision", &text[..i])?;
+        }
+    }
+
+    match text[i..].chars().next() {
+        Some(c) if "sbcdiuoxXfFeE".contains(c) => {
+            spec.conversion = c;
+            Ok((spec, i + 1))
+        }
+        Some(c) => Err(From::from(format!(
+            "invalid conversion specification \"{}{}\"",
+            &text[..i],
+            c
+        ))),
+        None => Err(From::from(format!(
+            "missing format character in \"{}\"",
+            text
+        ))),
+    }
+}
+
+// --------------------------------------------------
+// Write one directive into `out`. Returns true when a `\c` inside a %b
+// argument asks for all further output to be dropped.
+fn format_spec(
+    spec: &Spec,
+    args: &[String],
+    used: &mut usize,
+    out: &mut Vec<u8>,
+) -> MyResult<bool> {
+    let mut next_arg = || -> &str {
+        let arg = args.get(*used).map_or("", String::as_str);
+        *used += 1;
+        arg
+    };
+
+    let mut left = spec.left;
+    let width = match spec.width {
+        Some(FromArg) => {
+            // 负的宽度等于加了`-`
+            let arg = next_arg();
+            let val = parse_int(arg)?;
+            left |= val < 0;
+            check_count(val.unsigned_abs() as usize, "field width", arg)?
+        }
+        Some(Fixed(n)) => n,
+        None => 0,
+    };
+    let precision = match spec.precision {
+        // 负的精度当作没给
+        Some(FromArg) => {
+            let arg = next_arg();
+            match usize::try_from(parse_int(arg)?) {
+                Ok(n) => Some(check_count(n, "precision", arg)?),
+                Err(_) => None,
+            }
+        }
+        Some(Fixed(n)) => Some(n),
+        None => None,
+    };
+
+    let arg = next_arg();
+    match spec.conversion {
+        's' => {
+            // 和printf一样按字节截断，只是不切开一个字符
+            let text = match precision {
+                Some(p) => &arg[..arg.floor_char_boundary(p)],
+                None => arg,
+            };
+            out.extend_from_slice(pad("", text, width, left, false).as_bytes());
+        }
+        'c' => {
+            let text: String = arg.chars().take(1).collect();
+            out.extend_from_slice(pad("", &text, width, left, false).as_bytes());
+        }
+        'b' => {
+            let (mut bytes, stopped) = unescape(arg);
+            if let Some(p) = precision {
+                bytes.truncate(p);
+            }
+            let fill = vec![b' '; width.saturating_sub(bytes.len())];
+            if left {
+                out.extend(bytes.iter().chain(&fill));
+            } else {
+                out.extend(fill.iter().chain(&bytes));
+            }
+            return Ok(stopped);
+        }
+        'd' | 'i' | 'u' | 'o' | 'x' | 'X' => {
+            let val = parse_int(arg)?;
+            out.extend_from_slice(format_int(spec, width, left, precision, val).as_bytes());
+        }
+        _ => {
+            let val = parse_float(arg)?;
+            out.extend_from_slice(format_float(spec, width, left, precision, val).as_bytes());
+        }
+    }
+
+    Ok(false)
+}
+
+// --------------------------------------------------
+fn format_int(spec: &Spec, width: usize, left: bool, precision: Option<usize>, val: i64) -> String {
+    let (sign, mut digits) = match spec.conversion {
+        'd' | 'i' => (sign(spec, val < 0), val.unsigned_abs().to_string()),
+        // 无符号的转换，负数按64位补码处理，和GNU printf一样
+        'u' => ("", (val as u64).to_string()),
+        'o' => ("", format!("{:o}", val as u64)),
+        'x' => ("", format!("{:x}", val as u64)),
+        _ => ("", format!("{:X}", val as u64)),
+    };
+
+    if let Some(p) = precision {
+        if p == 0 && val == 0 {
+            digits.clear();
+        } else if digits.len() < p {
+            digits = format!("{}{}", "0".repeat(p - digits.len()), digits);
+        }
+    }
+
+    let prefix = match spec.conversion {
+        'o' if spec.alt && !digits.starts_with('0') => "0",
+        'x' if spec.alt && val != 0 => "0x",
+        'X' if spec.alt && val != 0 => "0X",
+        _ => sign,
+    };
+
+    // 有精度的时候0标志不起作用
+    pad(
+        prefix,
+        &digits,
+        width,
+        left,
+        spec.zero && precision.is_none(),
+    )
+}
+
+// --------------------------------------------------
+fn format_float(
+    spec: &Spec,
+    width: usize,
+    left: bool,
+    precision: Option<usize>,
+    val: f64,
+) -> String {
+    let precision = precision.unwrap_or(6);
+    let upper = spec.conversion.is_ascii_uppercase();
+    let digits = if val.is_nan() {
+        "nan".to_string()
+    } else if val.is_infinite() {
+        "inf".to_string()
+    } else if spec.conversion.eq_ignore_ascii_case(&'e') {
+        // Rust的{:e}是"1.5e2"，C要的是"1.500000e+02"
+        let formatted = format!("{:.*e}", precision, val.abs());
+        let (mantissa, exp) = formatted.split_once('e').unwrap();
+        let exp: i32 = exp.parse().unwrap();
+        format!(
+            "{}e{}{:02}",
+            mantissa,
+            if exp < 0 { '-' } else { '+' },
+            exp.abs()
+        )
+    } else {
+        format!("{:.*}", precision, val.abs())
+    };
+    let digits = if upper { digits.to_uppercase() } else { digits };
+
+    pad(
+        sign(spec, val.is_sign_negative() && !val.is_nan()),
+        &digits,
+        width,
+        left,
+        spec.zero && val.is_finite(),
+    )
+}
+
+// --------------------------------------------------
+fn sign(spec: &Spec, negative: bool) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+// --------------------------------------------------
+fn check_count(n: usize, what: &str, text: &str) -> MyResult<usize> {
+    if n > MAX_COUNT {
+        return Err(From::from(format!("invalid {}: \"{}\"", what, text)));
+    }
+    Ok(n)
+}
+
+// --------------------------------------------------
+// Pad `prefix` + `body` out to `width` bytes, as printf counts them. Zero
+// padding goes between the sign/prefix and the digits.
+fn pad(prefix: &str, body: &str, width: usize, left: bool, zero: bool) -> String {
+    let len = prefix.len() + body.len();
+    if len >= width {
+        return format!("{}{}", prefix, body);
+    }
+
+    let fill = width - len;
+    if left {
+        format!("{}{}{}", prefix, body, " ".repeat(fill))
+    } else if zero {
+        format!("{}{}{}", prefix, "0".repeat(fill), body)
+    } else {
+        format!("{}{}{}", " ".repeat(fill), prefix, body)
+    }
+}
+
+// --------------------------------------------------
+// printf numeric arguments: decimal, 0x hex, leading-0 octal, or 'c for the
+// code point of c. An empty argument is zero.
+fn parse_int(arg: &str) -> MyResult<i64> {
+    let value_error = || format!("\"{}\": expected a numeric value", arg);
+    let trimmed = arg.trim_start();
+    if trimmed.is_empty() {
+        return Ok(0);
+    }
+    if let Some(rest) = trimmed.strip_prefix(['\'', '"']) {
+        return Ok(rest.chars().next().map_or(0, |c| c as i64));
+    }
+
+    let (negative, digits) = match trimmed.as_bytes()[0] {
+        b'-' => (true, &trimmed[1..]),
+        b'+' => (false, &trimmed[1..]),
+        _ => (false, trimmed),
+    };
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+
+    parsed
+        .map(|n| if negative { n.wrapping_neg() } else { n })
+        .map_err(|_| From::from(value_error()))
+}
+
+// --------------------------------------------------
+fn parse_float(arg: &str) -> MyResult<f64> {
+    let trimmed = arg.trim();
+    if trimmed.is_empty() {
+        return Ok(0.0);
+    }
+    if trimmed.starts_with(['\'', '"']) {
+        return parse_int(arg).map(|n| n as f64);
+    }
+    trimmed
+        .parse()
+        .map_err(|_| From::from(format!("\"{}\": expected a numeric value", arg)))
+}
+
+// --------------------------------------------------
+// Interpret backslash escapes like GNU `echo -e`. The bool is true when a
+// `\c` was seen and the rest of the output (newline included) must be dropped.
+fn unescape(text: &str) -> (Vec<u8>, bool) {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        match push_escape(&bytes[i + 1..], false, &mut out) {
+            Some(len) => i += len + 1,
+            None => return (out, true),
+        }
+    }
+
+    (out, false)
+}
+
+// --------------------------------------------------
+// Decode the escape that follows a backslash into `out` and return how many
+// bytes it used, or None for `\c`. echo spells octal as `\0NNN`, while printf
+// format strings use `\NNN`.
+fn push_escape(bytes: &[u8], printf_octal: bool, out: &mut Vec<u8>) -> Option<usize> {
+    let mut len = 1;
+    match bytes[0] {
+        b'\\' => out.push(b'\\'),
+        b'a' => out.push(0x07),
+        b'b' => out.push(0x08),
+        b'c' => return None,
+        b'e' => out.push(0x1b),
+        b'f' => out.push(0x0c),
+        b'n' => out.push(b'\n'),
+        b'r' => out.push(b'\r'),
+        b't' => out.push(b'\t'),
+        b'v' => out.push(0x0b),
+        b'0'..=b'7' if printf_octal => {
+            let (val, digits) = parse_digits(bytes, 8, 3);
+            out.push(val as u8);
+            len = digits;
+        }
+        b'0' => {
+            // \0NNN: 最多三位八进制
+            let (val, digits) = parse_digits(&bytes[1..], 8, 3);
+            out.push(val as u8);
+            len += digits;
+        }
+        b'x' => {
+            // \xHH: 一到两位十六进制，没有数字就原样输出
+            let (val, digits) = parse_digits(&bytes[1..], 16, 2);
+            if digits == 0 {
+                out.extend_from_slice(b"\\x");
+            } else {
+                out.push(val as u8);
+                len += digits;
+            }
+        }
+        b'u' | b'U' => {
+            let max = if bytes[0] == b'u' { 4 } else { 8 };
+            let (val, digits) = parse_digits(&bytes[1..], 16, max);
+            match char::from_u32(val).filter(|_| digits > 0) {
+                Some(c) => {
+                    let mut buf = [0; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    len += digits;
+                }
+                None => {
+                    out.push(b'\\');
+                    out.push(bytes[0]);
+                }
+            }
+        }
+        // 不认识的转义原样保留
+        other => {
+            out.push(b'\\');
+            out.push(other);
+        }
+    }
+    Some(len)
+}
+
+// --------------------------------------------------
+// Read up to `max` digits in the given radix, returning the value and how many
+// bytes were consumed.
+fn parse_digits(bytes: &[u8], radix: u32, max: usize) -> (u32, usize) {
+    let mut val: u32 = 0;
+    let mut len = 0;
+    for &b in bytes.iter().take(max) {
+        match (b as char).to_digit(radix) {
+            Some(d) => {
+                val = val.saturating_mul(radix).saturating_add(d);
+                len += 1;
+            }
+            None => break,
+        }
+    }
+    (val, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_format, parse_int, printf, run, unescape, Config, Count::*, Piece::*, Spec};
+
+    fn echo(text: &[&str], omit_newline: bool, escapes: bool) -> Vec<u8> {
+        let config = Config {
+            text: text.iter().map(|s| s.to_string()).collect(),
+            omit_newline,
+            escapes,
+            separator: " ".to_string(),
+            terminator: "\n".to_string(),
+            format: None,
+        };
+        let mut out = vec![];
+        assert!(run(config, &mut out).is_ok());
+        out
+    }
+
+    #[test]
+    fn test_run() {
+        assert_eq!(echo(&["Hello", "there"], false, false), b"Hello there\n");
+        assert_eq!(echo(&["Hello  there"], true, false), b"Hello  there");
+        // 没有-e的时候反斜杠原样输出
+        assert_eq!(echo(&[r"a\tb"], false, false), b"a\\tb\n");
+        assert_eq!(echo(&[r"a\tb"], false, true), b"a\tb\n");
+        // \c 连换行都不要了
+        assert_eq!(echo(&[r"a\cb", "c"], false, true), b"a");
+    }
+
+    #[test]
+    fn test_run_separator() {
+        let config = Config {
+            text: vec!["a b".to_string(), r"c\t".to_string()],
+            omit_newline: false,
+            escapes: true,
+            separator: "\0".to_string(),
+            terminator: "\0".to_string(),
+            format: None,
+        };
+        let mut out = vec![];
+        assert!(run(config, &mut out).is_ok());
+        assert_eq!(out, b"a b\0c\t\0");
+
+        // The separator itself is never unescaped
+        let config = Config {
+            text: vec!["a".to_string(), "b".to_string()],
+            omit_newline: true,
+            escapes: true,
+            separator: r"\t".to_string(),
+            terminator: "\n".to_string(),
+            format: None,
+        };
+        let mut out = vec![];
+        assert!(run(config, &mut out).is_ok());
+        assert_eq!(out, b"a\\tb");
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape(r"a\nb"), (b"a\nb".to_vec(), false));
+        assert_eq!(unescape(r"\\"), (b"\\".to_vec(), false));
+        assert_eq!(
+            unescape(r"\a\b\e\f\r\v"),
+            (b"\x07\x08\x1b\x0c\r\x0b".to_vec(), false)
+        );
+        // Octal takes at most three digits after the \0
+        assert_eq!(unescape(r"\0101"), (b"A".to_vec(), false));
+        assert_eq!(unescape(r"\01012"), (b"A2".to_vec(), false));
+        assert_eq!(unescape(r"\0"), (b"\0".to_vec(), false));
+        // Hex takes one or two digits and may produce non-UTF-8 bytes
+        assert_eq!(unescape(r"\x41\xff\x4"), (b"A\xff\x04".to_vec(), false));
+        assert_eq!(unescape(r"\xg"), (b"\\xg".to_vec(), false));
+        assert_eq!(unescape(r"\u263A"), ("☺".as_bytes().to_vec(), false));
+        assert_eq!(unescape(r"\U0001F600"), ("😀".as_bytes().to_vec(), false));
+        // Unknown escapes and a trailing backslash are kept
+        assert_eq!(unescape(r"\q\"), (b"\\q\\".to_vec(), false));
+        assert_eq!(unescape(r"ab\cde"), (b"ab".to_vec(), true));
+    }
+
+    fn fmt(format: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        String::from_utf8(printf(format, &args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let res = parse_format(r"%-5s|%08.3f\n");
+        assert!(res.is_ok());
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Spec(Spec {
+                    left: true,
+                    width: Some(Fixed(5)),
+                    conversion: 's',
+                    ..Default::default()
+                }),
+                Literal(b"|".to_vec()),
+                Spec(Spec {
+                    zero: true,
+                    width: Some(Fixed(8)),
+                    precision: Some(Fixed(3)),
+                    conversion: 'f',
+                    ..Default::default()
+                }),
+                Literal(b"\n".to_vec()),
+            ]
+        );
+
+        let res = parse_format(r"%*.*d%% \101\c ignored");
+        assert!(res.is_ok());
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Spec(Spec {
+                    width: Some(FromArg),
+                    precision: Some(FromArg),
+                    conversion: 'd',
+                    ..Default::default()
+                }),
+                Literal(b"% A".to_vec()),
+                Stop,
+            ]
+        );
+
+        let res = parse_format("%q");
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "invalid conversion specification \"%q\""
+        );
+
+        let res = parse_format("abc %-");
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "missing format character in \"%-\""
+        );
+    }
+
+    #[test]
+    fn test_parse_int() {
+        assert_eq!(parse_int("42").unwrap(), 42);
+        assert_eq!(parse_int(" -42").unwrap(), -42);
+        assert_eq!(parse_int("0x1f").unwrap(), 31);
+        assert_eq!(parse_int("017").unwrap(), 15);
+        assert_eq!(parse_int("'A").unwrap(), 65);
+        assert_eq!(parse_int("").unwrap(), 0);
+        let res = parse_int("4x");
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "\"4x\": expected a numeric value"
+        );
+    }
+
+    #[test]
+    fn test_printf() {
+        assert_eq!(fmt("%s\n", &[]), "\n");
+        assert_eq!(
+            fmt("[%5s][%-5s][%.2s]", &["ab", "cd", "efg"]),
+            "[   ab][cd   ][ef]"
+        );
+        // Width and precision count bytes like printf, but never split a char
+        assert_eq!(fmt("%.2s|%.3s|%4s|", &["é…", "é…", "é"]), "é|é|  é|");
+        assert_eq!(fmt("%.5s|%-4s|", &["é…", "…"]), "é…|… |");
+        // The format is reused until the arguments run out
+        assert_eq!(fmt("%s=%d\n", &["a", "1", "b"]), "a=1\nb=0\n");
+        assert_eq!(fmt("no args\n", &["x", "y"]), "no args\n");
+        assert_eq!(
+            fmt(
+                "%d|%+d|% d|%05d|%-5d|%.3d",
+                &["7", "7", "7", "-7", "7", "7"]
+            ),
+            "7|+7| 7|-0007|7    |007"
+        );
+        assert_eq!(
+            fmt(
+                "%x %X %#x %o %#o %u",
+                &["255", "255", "255", "8", "8", "-1"]
+            ),
+            "ff FF 0xff 10 010 18446744073709551615"
+        );
+        assert_eq!(
+            fmt(
+                "%f|%.2f|%8.3f|%-8.1f|%e|%E",
+                &["1.5", "2.345", "-3.14159", "2", "1234.5", "0.00012"]
+            ),
+            "1.500000|2.35|  -3.142|2.0     |1.234500e+03|1.200000E-04"
+        );
+        assert_eq!(
+            fmt("%*d|%-*d|%.*f", &["4", "1", "3", "2", "1", "9.99"]),
+            "   1|2  |10.0"
+        );
+        assert_eq!(fmt("%c%c", &["hello", "☺"]), "h☺");
+        assert_eq!(fmt(r"%b|%s", &[r"a\tb", r"a\tb"]), "a\tb|a\\tb");
+        // \c inside %b stops everything
+        assert_eq!(fmt(r"%b-%s\n", &[r"x\cy", "z"]), "x");
+        assert!(printf("%d", &["abc".to_string()]).is_err());
+    }
+}
@@ -43,4 +43,77 @@ fn hello1_no_newline() -> TestResult {
 #[test]
 fn hello2_no_newline() -> TestResult {
     run(&["-n", "Hello", "there"], "tests/expected/hello2.n.txt")
-}
\ No newline at end of file
+}
+#[test]
+fn escapes() -> TestResult {
+    run(&["-e", r"Hello\tthere\\\nworld"], "tests/expected/escapes.e.txt")
+}
+
+// -E 在后面，关掉转义
+#[test]
+fn escapes_disabled() -> TestResult {
+    run(&["-e", "-E", r"Hello\tthere\\\nworld"], "tests/expected/escapes.E.txt")
+}
+
+#[test]
+fn escape_codes() -> TestResult {
+    run(&["-e", r"\0101\x42\u263A\a"], "tests/expected/codes.e.txt")
+}
+
+#[test]
+fn escape_stop() -> TestResult {
+    run(&["-e", r"Hello\cthere"], "tests/expected/stop.e.txt")
+}
+
+#[test]
+fn format() -> TestResult {
+    run(
+        &["--format", r"%-6s|%5.2f|%04x\n", "apple", "3.14159", "255", "banana", "2", "4096"],
+        "tests/expected/format.txt",
+    )
+}
+
+#[test]
+fn format_b() -> TestResult {
+    run(&["-f", r"%s %b\n", "tab", r"a\tb"], "tests/expected/format.b.txt")
+}
+
+#[test]
+fn dies_bad_number() -> TestResult {
+    Command::cargo_bin("echor")?
+        .args(["-f", "%d", "abc"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("\"abc\": expected a numeric value"));
+    Ok(())
+}
+
+#[test]
+fn dies_huge_width() -> TestResult {
+    for (format, arg, expected) in [
+        ("%99999999999d", "1", "invalid field width: \"%99999999999\""),
+        ("%.99999999999f", "1", "invalid precision: \"%.99999999999\""),
+        ("%*s", "4000000000", "invalid field width: \"4000000000\""),
+        ("%.*s", "4000000000", "invalid precision: \"4000000000\""),
+    ] {
+        Command::cargo_bin("echor")?
+            .args(["-f", format, arg, "x"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(expected));
+    }
+    Ok(())
+}
+
+#[test]
+fn zero_terminated() -> TestResult {
+    run(&["-z", "Hello", "my friend"], "tests/expected/hello.z.txt")
+}
+
+#[test]
+fn separator_terminator() -> TestResult {
+    run(
+        &["--separator", ", ", "--terminator", ";", "a", "b", "c"],
+        "tests/expected/abc.sep.txt",
+    )
+}
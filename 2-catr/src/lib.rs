@@ -1,21 +1,109 @@
+use crate::Compression::*;
+use bzip2::read::MultiBzDecoder;
 use clap::{App, Arg};
+use flate2::read::MultiGzDecoder;
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write};
+use xz2::read::XzDecoder;
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
+// 读写都用大一点的缓冲，拼接大文件的时候少一些系统调用
+const BUF_SIZE: usize = 64 * 1024;
+// 格式化的宽度最大只能到u16::MAX
+const MAX_WIDTH: usize = u16::MAX as usize;
+
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
     number_lines: bool,
     number_nonblank_lines: bool,
+    show_ends: bool,
+    show_tabs: bool,
+    show_nonprinting: bool,
+    squeeze_blank: bool,
+    decompress: bool,
+    continuous: bool,
+    start: i64,
+    increment: i64,
+    width: usize,
+    separator: String,
+}
+
+// 行号和空行的状态，--continuous的时候跨文件保留
+#[derive(Debug)]
+struct LineState {
+    // 加溢出了就是None，真要用到这个号的时候再报错
+    next_num: Option<i64>,
+    prev_blank: bool,
+    // 上个文件最后一行没有换行的话，下个文件的第一行算是接着写的
+    at_line_start: bool,
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+impl LineState {
+    fn new(config: &Config) -> Self {
+        LineState {
+            next_num: Some(config.start),
+            prev_blank: false,
+            at_line_start: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+fn open(filename: &str, decompress: bool) -> MyResult<Box<dyn BufRead>> {
+    let file: Box<dyn Read> = match filename {
+        "-" => Box::new(io::stdin()),
+        _ => Box::new(File::open(filename)?),
+    };
+    if decompress {
+        decoder(file)
+    } else {
+        Ok(Box::new(BufReader::with_capacity(BUF_SIZE, file)))
+    }
+}
+
+// --------------------------------------------------
+// Sniff the magic bytes and wrap the input in the matching decoder, like
+// zcat/bzcat/xzcat. Anything unrecognized passes through as is.
+fn decoder(mut file: Box<dyn Read>) -> MyResult<Box<dyn BufRead>> {
+    // stdin没法seek，读出来的头再用chain接回去
+    let mut header = Vec::with_capacity(6);
+    file.by_ref().take(6).read_to_end(&mut header)?;
+    let compression = detect(&header);
+    let input = Cursor::new(header).chain(file);
+
+    let reader: Box<dyn Read> = match compression {
+        // Multi*的解码器能处理多个拼在一起的压缩流
+        Some(Gzip) => Box::new(MultiGzDecoder::new(input)),
+        Some(Bzip2) => Box::new(MultiBzDecoder::new(input)),
+        Some(Xz) => Box::new(XzDecoder::new_multi_decoder(input)),
+        Some(Zstd) => Box::new(zstd::Decoder::new(input)?),
+        None => Box::new(input),
+    };
+    Ok(Box::new(BufReader::with_capacity(BUF_SIZE, reader)))
+}
+
+// --------------------------------------------------
+fn detect(header: &[u8]) -> Option<Compression> {
+    if header.starts_with(&[0x1f, 0x8b]) {
+        Some(Gzip)
+    } else if header.starts_with(b"BZh") {
+        Some(Bzip2)
+    } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Some(Xz)
+    } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(Zstd)
+    } else {
+        None
     }
 }
 
@@ -50,41 +138,404 @@ pub fn get_args() -> MyResult<Config> {
                 .help("Number non-blank lines")
                 .takes_value(false),
             )
+            .arg(
+                Arg::with_name("show_all")
+                .short("A")
+                .long("show-all")
+                .help("Equivalent to -vET")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("ends_nonprinting")
+                .short("e")
+                .help("Equivalent to -vE")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("show_ends")
+                .short("E")
+                .long("show-ends")
+                .help("Display $ at end of each line")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("squeeze_blank")
+                .short("s")
+                .long("squeeze-blank")
+                .help("Suppress repeated empty output lines")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("tabs_nonprinting")
+                .short("t")
+                .help("Equivalent to -vT")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("show_tabs")
+                .short("T")
+                .long("show-tabs")
+                .help("Display TAB characters as ^I")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("show_nonprinting")
+                .short("v")
+                .long("show-nonprinting")
+                .help("Use ^ and M- notation, except for LFD and TAB")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("decompress")
+                .short("z")
+                .long("decompress")
+                .help("Decompress gzip, bzip2, xz and zstd input")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("continuous")
+                .long("continuous")
+                .help("Keep numbering lines across files")
+                .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("start")
+                .long("starting-line-number")
+                .value_name("NUMBER")
+                .help("First line number")
+                .default_value("1"),
+            )
+            .arg(
+                Arg::with_name("increment")
+                .long("line-increment")
+                .value_name("NUMBER")
+                .help("Line number increment")
+                .default_value("1"),
+            )
+            .arg(
+                Arg::with_name("width")
+                .long("number-width")
+                .value_name("NUMBER")
+                .help("Width of line numbers")
+                .default_value("6"),
+            )
+            .arg(
+                Arg::with_name("separator")
+                .long("number-separator")
+                .value_name("STRING")
+                .help("Separator after line numbers [default: TAB]")
+                .default_value("\t")
+                .hide_default_value(true),
+            )
             .get_matches();
 
+    // -A, -e, -t 都是组合开关
+    let show_all = matches.is_present("show_all");
+    let ends_nonprinting = matches.is_present("ends_nonprinting");
+    let tabs_nonprinting = matches.is_present("tabs_nonprinting");
+
+    let start = matches
+        .value_of("start")
+        .map(parse_int)
+        .transpose()
+        .map_err(|e| format!("illegal starting line number -- {}", e))?;
+    let increment = matches
+        .value_of("increment")
+        .map(parse_int)
+        .transpose()
+        .map_err(|e| format!("illegal line increment -- {}", e))?;
+    let width = matches
+        .value_of("width")
+        .map(|val| match parse_positive_int(val) {
+            Ok(n) if n > MAX_WIDTH => Err(From::from(val)),
+            res => res,
+        })
+        .transpose()
+        .map_err(|e| format!("illegal line number width -- {}", e))?;
+
     Ok(Config {
         files: matches.values_of_lossy("files").unwrap(),
         number_lines: matches.is_present("number"),
         number_nonblank_lines: matches.is_present("number_nonblank") ,
+        show_ends: show_all || ends_nonprinting || matches.is_present("show_ends"),
+        show_tabs: show_all || tabs_nonprinting || matches.is_present("show_tabs"),
+        show_nonprinting: show_all
+            || ends_nonprinting
+            || tabs_nonprinting
+            || matches.is_present("show_nonprinting"),
+        squeeze_blank: matches.is_present("squeeze_blank"),
+        decompress: matches.is_present("decompress"),
+        continuous: matches.is_present("continuous"),
+        start: start.unwrap(),
+        increment: increment.unwrap(),
+        width: width.unwrap(),
+        separator: matches.value_of("separator").unwrap().to_string(),
     })
 }
 
 
-pub fn run(config: Config) -> MyResult<()> {
+// 返回失败的文件数，每个都已经报过了，main用它决定退出码
+pub fn run(config: Config) -> MyResult<usize> {
     // TODO: dbg?print?
     // dbg!(config);
-    for filename in config.files {
-        match open(&filename) {
-            Err(err) => eprintln!("Failed to open {}: {}", filename, err),
+    let stdout = io::stdout();
+    let mut out = BufWriter::with_capacity(BUF_SIZE, stdout.lock());
+    // 什么都不用改的时候直接按字节拷贝，输入是什么输出就是什么
+    let plain = !config.number_lines
+        && !config.number_nonblank_lines
+        && !config.show_ends
+        && !config.show_tabs
+        && !config.show_nonprinting
+        && !config.squeeze_blank;
+    let mut state = LineState::new(&config);
+    let mut num_failed = 0;
+
+    for filename in &config.files {
+        let result = match open(filename, config.decompress) {
+            Err(err) => Err(format!("Failed to open {}: {}", filename, err)),
+            Ok(file) if plain => copy(file, &mut out)
+                .map_err(|e| format!("{}: {}", filename, e)),
             Ok(file) => {
-                let mut last_num = 0;
-                for (line_num, line_result) in file.lines().enumerate() {
-                    let line = line_result?;
-                    if config.number_lines {
-                        println!("{:6}\t{}", line_num + 1, line);
-                    } else if config.number_nonblank_lines {
-                        if !line.is_empty() {
-                            last_num += 1;
-                            println!("{:6}\t{}", last_num, line);
-                        } else {
-                            println!();
-                        }
-                    } else {
-                        println!("{}", line);
-                    }
+                if !config.continuous {
+                    state = LineState::new(&config);
                 }
+                cat_lines(file, &config, &mut state, &mut out)
+                    .map_err(|e| format!("{}: {}", filename, e))
             }
+        };
+
+        // 一个文件出错不影响后面的文件
+        if let Err(e) = result {
+            // 先把之前的输出刷出去，错误信息的位置才对得上
+            out.flush()?;
+            eprintln!("{}", e);
+            num_failed += 1;
         }
     }
+    out.flush()?;
+
+    Ok(num_failed)
+}
+
+// --------------------------------------------------
+// Copy the input through unchanged, flushing after every read so that slow
+// input like `tail -f log | catr` shows up as soon as it arrives.
+fn copy(mut file: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+    loop {
+        let buf = file.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let len = buf.len();
+        out.write_all(buf)?;
+        out.flush()?;
+        file.consume(len);
+    }
+}
+
+// --------------------------------------------------
+fn cat_lines(
+    mut file: impl BufRead,
+    config: &Config,
+    state: &mut LineState,
+    mut out: impl Write,
+) -> MyResult<()> {
+    // 按字节读，-v要看到每个字节，-E要看到\r
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if file.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+
+        let continued = !state.at_line_start;
+        state.at_line_start = line.ends_with(b"\n");
+        let blank = !continued && line == b"\n";
+        if config.squeeze_blank && blank && state.prev_blank {
+            continue;
+        }
+        state.prev_blank = blank;
+
+        if !continued && (config.number_lines || (config.number_nonblank_lines && !blank)) {
+            let num = state.next_num.ok_or("line number overflow")?;
+            write!(
+                out,
+                "{:>width$}{}",
+                num,
+                config.separator,
+                width = config.width
+            )?;
+            state.next_num = num.checked_add(config.increment);
+        }
+        write_line(&mut out, &line, config)?;
+    }
     Ok(())
 }
+
+// --------------------------------------------------
+fn parse_int(val: &str) -> MyResult<i64> {
+    val.parse().map_err(|_| From::from(val))
+}
+
+// --------------------------------------------------
+fn parse_positive_int(val: &str) -> MyResult<usize> {
+    match val.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(From::from(val)),
+    }
+}
+
+// --------------------------------------------------
+// Write one line (including its newline, if any) applying -E, -T and -v.
+fn write_line(mut out: impl Write, line: &[u8], config: &Config) -> MyResult<()> {
+    if !config.show_ends && !config.show_tabs && !config.show_nonprinting {
+        out.write_all(line)?;
+        return Ok(());
+    }
+
+    let (body, ending): (&[u8], &[u8]) = if !config.show_ends {
+        (line, b"")
+    } else if let Some(body) = line.strip_suffix(b"\r\n") {
+        // 和GNU cat一样，CRLF显示成^M$
+        (body, b"^M$\n")
+    } else if let Some(body) = line.strip_suffix(b"\n") {
+        (body, b"$\n")
+    } else {
+        (line, b"")
+    };
+
+    let mut buf = Vec::with_capacity(line.len() + 8);
+    for &byte in body {
+        match byte {
+            b'\t' if config.show_tabs => buf.extend_from_slice(b"^I"),
+            b'\t' | b'\n' => buf.push(byte),
+            _ if config.show_nonprinting => push_nonprinting(&mut buf, byte),
+            _ => buf.push(byte),
+        }
+    }
+    buf.extend_from_slice(ending);
+    out.write_all(&buf)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+// Caret and M- notation used by cat -v.
+fn push_nonprinting(buf: &mut Vec<u8>, byte: u8) {
+    let byte = if byte >= 128 {
+        buf.extend_from_slice(b"M-");
+        byte - 128
+    } else {
+        byte
+    };
+    match byte {
+        0..=31 => buf.extend_from_slice(&[b'^', byte + 64]),
+        127 => buf.extend_from_slice(b"^?"),
+        _ => buf.push(byte),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cat_lines, detect, parse_positive_int, push_nonprinting, write_line, Compression::*, Config,
+        LineState,
+    };
+    use std::io::Cursor;
+
+    fn show(line: &[u8], show_ends: bool, show_tabs: bool, show_nonprinting: bool) -> Vec<u8> {
+        let config = Config {
+            files: vec![],
+            number_lines: false,
+            number_nonblank_lines: false,
+            show_ends,
+            show_tabs,
+            show_nonprinting,
+            squeeze_blank: false,
+            decompress: false,
+            continuous: false,
+            start: 1,
+            increment: 1,
+            width: 6,
+            separator: "\t".to_string(),
+        };
+        let mut out = vec![];
+        assert!(write_line(&mut out, line, &config).is_ok());
+        out
+    }
+
+    #[test]
+    fn test_write_line() {
+        assert_eq!(show(b"a\tb\r\n", false, false, false), b"a\tb\r\n");
+        assert_eq!(show(b"a\tb\r\n", true, false, false), b"a\tb^M$\n");
+        assert_eq!(show(b"a\tb\n", false, true, false), b"a^Ib\n");
+        assert_eq!(show(b"a\tb\x01\r\n", false, false, true), b"a\tb^A^M\n");
+        assert_eq!(show(b"a\tb\x01\r\n", true, true, true), b"a^Ib^A^M$\n");
+        // No newline, no $
+        assert_eq!(show(b"end", true, false, false), b"end");
+    }
+
+    #[test]
+    fn test_push_nonprinting() {
+        let notation = |byte| {
+            let mut buf = vec![];
+            push_nonprinting(&mut buf, byte);
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!(notation(b'a'), "a");
+        assert_eq!(notation(0), "^@");
+        assert_eq!(notation(0x1b), "^[");
+        assert_eq!(notation(127), "^?");
+        assert_eq!(notation(0xe2), "M-b");
+        assert_eq!(notation(0x80), "M-^@");
+        assert_eq!(notation(0xff), "M-^?");
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(&[0x1f, 0x8b, 0x08, 0x00]), Some(Gzip));
+        assert_eq!(detect(b"BZh91AY"), Some(Bzip2));
+        assert_eq!(detect(b"\xfd7zXZ\x00"), Some(Xz));
+        assert_eq!(detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Some(Zstd));
+        // Too short or plain text is not compressed
+        assert_eq!(detect(b"\xfd7zX"), None);
+        assert_eq!(detect(b"BZ"), None);
+        assert_eq!(detect(b""), None);
+        assert_eq!(detect(b"The bustle"), None);
+    }
+
+    #[test]
+    fn test_cat_lines() {
+        let config = Config {
+            files: vec![],
+            number_lines: true,
+            number_nonblank_lines: false,
+            show_ends: false,
+            show_tabs: false,
+            show_nonprinting: false,
+            squeeze_blank: false,
+            decompress: false,
+            continuous: true,
+            start: 10,
+            increment: 5,
+            width: 3,
+            separator: ": ".to_string(),
+        };
+        let mut state = LineState::new(&config);
+        let mut out = vec![];
+        assert!(cat_lines(Cursor::new("a\n\nb"), &config, &mut state, &mut out).is_ok());
+        // The unterminated "b" continues into the next file without a number
+        assert!(cat_lines(Cursor::new("c\nd\n"), &config, &mut state, &mut out).is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            " 10: a\n 15: \n 20: bc\n 25: d\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_positive_int() {
+        assert_eq!(parse_positive_int("3").unwrap(), 3);
+        assert!(parse_positive_int("0").is_err());
+        assert!(parse_positive_int("-3").is_err());
+        assert_eq!(parse_positive_int("foo").unwrap_err().to_string(), "foo");
+    }
+}
@@ -11,6 +11,9 @@ const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const SPIDERS: &str = "tests/inputs/spiders.txt";
 const BUSTLE: &str = "tests/inputs/the-bustle.txt";
+const BLANKS: &str = "tests/inputs/blanks.txt";
+const CONTROL: &str = "tests/inputs/control.txt";
+const BINARY: &str = "tests/inputs/binary.bin";
 
 // --------------------------------------------------
 #[test]
@@ -47,11 +50,37 @@ fn skips_bad_file() -> TestResult {
     Command::cargo_bin(PRG)?
         .arg(&bad)
         .assert()
-        .success()
+        .failure()
         .stderr(predicate::str::is_match(expected)?);
     Ok(())
 }
 
+// --------------------------------------------------
+#[test]
+fn bad_file_keeps_going() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!("^Failed to open {}: .* [(]os error 2[)]\n$", bad);
+    Command::cargo_bin(PRG)?
+        .args([FOX, &bad, SPIDERS])
+        .assert()
+        .code(1)
+        .stdout(fs::read_to_string("tests/expected/fox.spiders.out")?)
+        .stderr(predicate::str::is_match(expected)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn directory_is_an_error() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", FOX])
+        .assert()
+        .code(1)
+        .stdout(fs::read_to_string("tests/expected/fox.txt.out")?)
+        .stderr(predicate::str::contains("tests/inputs: Is a directory"));
+    Ok(())
+}
+
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
     let expected = fs::read_to_string(expected_file)?;
@@ -63,6 +92,17 @@ fn run(args: &[&str], expected_file: &str) -> TestResult {
     Ok(())
 }
 
+// --------------------------------------------------
+// Compare raw bytes, for output that is not valid UTF-8
+fn run_bytes(args: &[&str], expected: Vec<u8>) -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
 // --------------------------------------------------
 fn run_stdin(
     input_file: &str,
@@ -198,3 +238,258 @@ fn all_n() -> TestResult {
 fn all_b() -> TestResult {
     run(&[FOX, SPIDERS, BUSTLE, "-b"], "tests/expected/all.b.out")
 }
+
+// --------------------------------------------------
+#[test]
+fn bustle_e_upper() -> TestResult {
+    run(&["-E", BUSTLE], "tests/expected/the-bustle.txt.E.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_t_upper() -> TestResult {
+    run(&["--show-tabs", BLANKS], "tests/expected/blanks.txt.T.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_s() -> TestResult {
+    run(&["-s", BLANKS], "tests/expected/blanks.txt.s.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_ns() -> TestResult {
+    run(&["-n", "-s", BLANKS], "tests/expected/blanks.txt.ns.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_bs() -> TestResult {
+    run(&["-bs", BLANKS], "tests/expected/blanks.txt.bs.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_s_e_upper() -> TestResult {
+    run(&["--squeeze-blank", "-E", BLANKS], "tests/expected/blanks.txt.sE.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_a_upper() -> TestResult {
+    run(&["-A", CONTROL], "tests/expected/control.txt.A.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_v() -> TestResult {
+    run(&["-v", CONTROL], "tests/expected/control.txt.v.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_e() -> TestResult {
+    run(&["-e", CONTROL], "tests/expected/control.txt.e.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_t() -> TestResult {
+    run(&["-t", CONTROL], "tests/expected/control.txt.t.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_sb_a_upper() -> TestResult {
+    run(&["-s", "-b", "--show-all", CONTROL], "tests/expected/control.txt.sbA.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn binary() -> TestResult {
+    run_bytes(&[BINARY], fs::read(BINARY)?)
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_stdin() -> TestResult {
+    let input = fs::read(BINARY)?;
+    Command::cargo_bin(PRG)?
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(input);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_concat() -> TestResult {
+    let mut expected = fs::read(BINARY)?;
+    expected.extend(fs::read(CONTROL)?);
+    expected.extend(fs::read(BINARY)?);
+    run_bytes(&[BINARY, CONTROL, BINARY], expected)
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_n() -> TestResult {
+    run_bytes(&["-n", BINARY], fs::read("tests/expected/binary.bin.n.out")?)
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_decompress() -> TestResult {
+    for ext in &["gz", "bz2", "xz", "zst"] {
+        let filename = format!("{}.{}", BUSTLE, ext);
+        run(&["--decompress", &filename], "tests/expected/the-bustle.txt.out")?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_decompress_n() -> TestResult {
+    run(
+        &["-z", "-n", "tests/inputs/the-bustle.txt.xz"],
+        "tests/expected/the-bustle.txt.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_decompress_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("-z")
+        .write_stdin(fs::read("tests/inputs/the-bustle.txt.zst")?)
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("tests/expected/the-bustle.txt.out")?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn decompress_multi_member() -> TestResult {
+    let mut expected = fs::read(FOX)?;
+    expected.extend(fs::read(SPIDERS)?);
+    run_bytes(&["-z", "tests/inputs/fox-spiders.txt.gz"], expected)
+}
+
+// --------------------------------------------------
+#[test]
+fn decompress_passes_plain_input() -> TestResult {
+    run_bytes(&["-z", BINARY, EMPTY], fs::read(BINARY)?)
+}
+
+// --------------------------------------------------
+#[test]
+fn compressed_without_flag() -> TestResult {
+    let gz = format!("{}.gz", BUSTLE);
+    run_bytes(&[&gz], fs::read(&gz)?)
+}
+
+// --------------------------------------------------
+#[test]
+fn all_n_continuous() -> TestResult {
+    run(
+        &["-n", "--continuous", FOX, SPIDERS, BUSTLE],
+        "tests/expected/all.n.continuous.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_control_b_continuous() -> TestResult {
+    run_bytes(
+        &["-b", "--continuous", BINARY, CONTROL],
+        fs::read("tests/expected/binary.control.b.continuous.out")?,
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_number_format() -> TestResult {
+    run(
+        &[
+            "-n",
+            "--starting-line-number",
+            "10",
+            "--line-increment",
+            "5",
+            "--number-width",
+            "3",
+            "--number-separator",
+            ": ",
+            BUSTLE,
+        ],
+        "tests/expected/the-bustle.txt.nl.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_number_width() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "--number-width", "0", FOX])
+        .assert()
+        .failure()
+        .stderr("illegal line number width -- 0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_huge_number_width() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "--number-width", "100000000000", FOX])
+        .assert()
+        .failure()
+        .stderr("illegal line number width -- 100000000000\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+// 和nl一样，号码能印出来就印，下一个溢出了才报错
+#[test]
+fn dies_line_number_overflow() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "--starting-line-number", "9223372036854775807"])
+        .write_stdin("a\nb\n")
+        .assert()
+        .code(1)
+        .stdout("9223372036854775807\ta\n")
+        .stderr("-: line number overflow\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+// stdin还开着的时候已经读到的内容就要输出，不能等到结束
+#[test]
+fn plain_streams_stdin() -> TestResult {
+    use std::io::{Read, Write};
+    use std::process::{Command, Stdio};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    stdin.write_all(b"partial")?;
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 7];
+        let _ = tx.send(stdout.read_exact(&mut buf).map(|_| buf));
+    });
+    let received = rx.recv_timeout(Duration::from_secs(10));
+
+    drop(stdin);
+    child.wait()?;
+    assert_eq!(&received??, b"partial");
+    Ok(())
+}
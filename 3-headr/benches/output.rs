@@ -0,0 +1,93 @@
+// 对比两种输出写法的耗时：
+//   print!      原来的写法，每行一次print!（每次都要锁stdout，遇到换行就flush一次）
+//   headr -n    现在的写法，锁一次stdout再包64K的BufWriter
+// 两边都把stdout接到/dev/null，读文件的方式也一样，差别只在输出这一段。
+//
+// 跑法：cargo bench --bench output
+// 每种写法跑RUNS次取最快的一次，结果打在stdout上。
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const LINES: usize = 2_000_000;
+const RUNS: usize = 5;
+// 设了这个环境变量就当成“旧版headr”来跑，把文件用print!原样打出来
+const OLD_PATH: &str = "HEADR_BENCH_PRINT";
+
+type MyResult<T> = Result<T, Box<dyn Error>>;
+
+fn main() -> MyResult<()> {
+    if let Ok(filename) = env::var(OLD_PATH) {
+        return print_lines(&filename);
+    }
+    // cargo test --benches也会跑到这里，那时候不带--bench，直接退出
+    if !env::args().any(|arg| arg == "--bench") {
+        return Ok(());
+    }
+
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("input.txt");
+    let size = write_input(&input)?;
+    let input = input.to_str().ok_or("non UTF-8 temp path")?;
+
+    let mut old = Command::new(env::current_exe()?);
+    old.env(OLD_PATH, input);
+    let mut new = Command::new(env!("CARGO_BIN_EXE_headr"));
+    new.args(["-n", "1G", input]);
+
+    println!("{} lines, {} bytes, best of {} runs", LINES, size, RUNS);
+    for (name, cmd) in [("print!", &mut old), ("BufWriter", &mut new)] {
+        let best = time(cmd)?;
+        println!(
+            "{:<10} {:>8.1} ms {:>8.1} MiB/s",
+            name,
+            best.as_secs_f64() * 1e3,
+            size as f64 / best.as_secs_f64() / (1024.0 * 1024.0)
+        );
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+// 原来head的输出方式：read_line读一行，print!打一行
+fn print_lines(filename: &str) -> MyResult<()> {
+    let mut file = BufReader::new(File::open(filename)?);
+    let mut line = String::new();
+    loop {
+        let bytes = file.read_line(&mut line)?;
+        if bytes == 0 {
+            break;
+        }
+        print!("{}", line);
+        line.clear();
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+fn write_input(path: &std::path::Path) -> io::Result<u64> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for i in 0..LINES {
+        writeln!(out, "{:>8} the quick brown fox jumps over the lazy dog", i)?;
+    }
+    out.into_inner()?.metadata().map(|meta| meta.len())
+}
+
+// --------------------------------------------------
+fn time(cmd: &mut Command) -> MyResult<Duration> {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let status = cmd.stdout(Stdio::null()).status()?;
+        let elapsed = start.elapsed();
+        if !status.success() {
+            return Err(format!("{:?} failed: {}", cmd, status).into());
+        }
+        best = best.min(elapsed);
+    }
+    Ok(best)
+}
@@ -1,15 +1,31 @@
+use crate::TakeValue::*;
 use clap::{App, Arg};
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
+const BUF_SIZE: usize = 64 * 1024;
+
+// -n/-c的值，负数表示除了最后K个都要
+#[derive(Debug, PartialEq)]
+enum TakeValue {
+    First(usize),
+    AllButLast(usize),
+}
+
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
-    lines: usize,
-    bytes: Option<usize>,
+    lines: TakeValue,
+    bytes: Option<TakeValue>,
+    chars: Option<TakeValue>,
+    quiet: bool,
+    verbose: bool,
+    // 行的结尾，-z的时候是NUL
+    delimiter: u8,
 }
 
 pub fn get_args() -> MyResult<Config> {
@@ -22,7 +38,9 @@ pub fn get_args() -> MyResult<Config> {
             .short("n")
             .long("lines")
             .value_name("LINES")
-            .help("Number of lines")
+            .help("Number of lines, or all but the last -K lines")
+            // -n -3 这种值也要能传进来
+            .allow_hyphen_values(true)
             .default_value("10")
         )
         .arg(
@@ -33,7 +51,38 @@ pub fn get_args() -> MyResult<Config> {
             // 可能没有设置defaultvalue，所以得设置takes_value
             .takes_value(true)
             .conflicts_with("lines")
-            .help("Number of bytes")
+            .allow_hyphen_values(true)
+            .help("Number of bytes, or all but the last -K bytes")
+        )
+        .arg(
+            Arg::with_name("chars")
+            .long("chars")
+            .value_name("CHARS")
+            .takes_value(true)
+            .conflicts_with_all(&["lines", "bytes"])
+            .allow_hyphen_values(true)
+            .help("Number of UTF-8 characters, or all but the last -K characters")
+        )
+        .arg(
+            Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .alias("silent")
+            .overrides_with("verbose")
+            .help("Never print headers giving file names")
+        )
+        .arg(
+            Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .overrides_with("quiet")
+            .help("Always print headers giving file names")
+        )
+        .arg(
+            Arg::with_name("zero_terminated")
+            .short("z")
+            .long("zero-terminated")
+            .help("Line delimiter is NUL, not newline")
         )
         .arg(
             Arg::with_name("files")
@@ -49,8 +98,8 @@ pub fn get_args() -> MyResult<Config> {
     let lines = matches
         // 返回的是Option<&str>
         .value_of("lines")
-        // map能拆开Option然后传给parse_positive_int
-        .map(parse_positive_int)
+        // map能拆开Option然后传给parse_take_value
+        .map(parse_take_value)
         // 上面返回是Option<Result>
         // transpose能把这个转换成Result<Option>
         .transpose()
@@ -59,50 +108,173 @@ pub fn get_args() -> MyResult<Config> {
 
     let bytes = matches
         .value_of("bytes")
-        .map(parse_positive_int)
+        .map(parse_take_value)
         .transpose()
         .map_err(|e| format!("illegal byte count -- {}", e))?;
 
+    let chars = matches
+        .value_of("chars")
+        .map(parse_take_value)
+        .transpose()
+        .map_err(|e| format!("illegal character count -- {}", e))?;
+
     Ok(Config{
         files: matches.values_of_lossy("files").unwrap(),
         lines: lines.unwrap(),
         bytes,
+        chars,
+        quiet: matches.is_present("quiet"),
+        verbose: matches.is_present("verbose"),
+        delimiter: if matches.is_present("zero_terminated") { b'\0' } else { b'\n' },
     })
 }
 
-pub fn run(config: Config) -> MyResult<()> {
+// 返回出错的文件数，错误信息在这里已经打印过
+pub fn run(config: Config) -> MyResult<usize> {
     // 或者使用dbg!
     // Ok(println!("{:#?}", config))
-    let num_files = config.files.len();
+    // 默认多个文件才打header，-q和-v可以强制关掉或者打开
+    let show_headers = !config.quiet && (config.verbose || config.files.len() > 1);
+    // 一次锁住stdout再包一层缓冲，不用每行都去抢锁和系统调用
+    let stdout = io::stdout();
+    let mut out = BufWriter::with_capacity(BUF_SIZE, stdout.lock());
+    let mut num_failed = 0;
     for (file_num, filename ) in config.files.iter().enumerate() {
-        match open(&filename) {
-            Err(err) => eprintln!("Failed to open{}: {}", filename, err),
-            Ok(mut file) => {
-                if num_files > 1 {
-                    println!(
+        let failure = match open(filename) {
+            Err(err) => format!("Failed to open {}: {}", filename, err),
+            Ok(file) => {
+                let res = if show_headers {
+                    writeln!(
+                        out,
                         "{}==> {} <==",
                         if file_num > 0 { "\n" } else { "" },
                         filename
-                    );
-                }
-                if let Some(num_bytes) = config.bytes {
-                    let mut handle = file.take(num_bytes as u64);
-                    let mut buffer = vec![0; num_bytes];
-                    let bytes_read = handle.read(&mut buffer)?;
-                    print!("{}", String::from_utf8_lossy(&buffer[..bytes_read]));
+                    )
+                    .map_err(From::from)
+                    .and_then(|_| head(file, &config, &mut out))
                 } else {
-                    let mut line = String::new();
-                    for _ in 0..config.lines {
-                        let bytes = file.read_line(&mut line)?;
-                        if bytes == 0 {
-                            break;
-                        }
-                        print!("{}", line);
-                        line.clear();
-                    }
+                    head(file, &config, &mut out)
+                };
+                match res {
+                    // 下游已经不读了（head | ...早早退出），没必要再往下做
+                    Err(err) if is_broken_pipe(&*err) => return Ok(num_failed),
+                    // 读的时候出错也只算这一个文件失败
+                    Err(err) => format!("{}: {}", filename, err),
+                    Ok(_) => continue,
+                }
+            }
+        };
+
+        // 先把前面文件的输出刷出去，错误信息才会出现在对的位置
+        match out.flush() {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(num_failed),
+            res => res?,
+        }
+        eprintln!("{}", failure);
+        num_failed += 1;
+    }
+
+    // 缓冲里剩下的要在process::exit之前刷出去
+    match out.flush() {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(num_failed),
+        res => res?,
+    }
+    Ok(num_failed)
+}
+
+// --------------------------------------------------
+fn is_broken_pipe(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+// --------------------------------------------------
+// Stops reading as soon as the requested amount has been written, so
+// `headr -n 1` on an endless pipe returns right away.
+fn head(mut file: impl BufRead, config: &Config, mut out: impl Write) -> MyResult<()> {
+    if let Some(num_chars) = &config.chars {
+        return match num_chars {
+            First(num_chars) => first_chars(file, *num_chars, out),
+            AllButLast(num_chars) => all_but_last_chars(file, *num_chars, out),
+        };
+    }
+
+    match (&config.bytes, &config.lines) {
+        (Some(First(num_bytes)), _) => {
+            // 原样输出字节，不再转成字符串，二进制和被截断的多字节字符都不会变样
+            io::copy(&mut file.take(*num_bytes as u64), &mut out)?;
+        }
+        (Some(AllButLast(num_bytes)), _) => all_but_last_bytes(file, *num_bytes, out)?,
+        (None, First(num_lines)) => {
+            let mut line = Vec::new();
+            for _ in 0..*num_lines {
+                let bytes = file.read_until(config.delimiter, &mut line)?;
+                if bytes == 0 {
+                    break;
                 }
+                out.write_all(&line)?;
+                line.clear();
             }
         }
+        (None, AllButLast(num_lines)) => all_but_last_lines(file, *num_lines, config.delimiter, out)?,
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+// Print everything except the last `num_lines` lines. Only the most recent
+// lines are kept in a ring buffer, so this works on pipes of any size.
+fn all_but_last_lines(
+    mut file: impl BufRead,
+    num_lines: usize,
+    delimiter: u8,
+    mut out: impl Write,
+) -> MyResult<()> {
+    let mut ring: VecDeque<Vec<u8>> = VecDeque::with_capacity(num_lines.min(1024) + 1);
+    loop {
+        // 复用从队头拿出来的buffer，少分配一点
+        let mut line = if ring.len() > num_lines {
+            ring.pop_front().unwrap()
+        } else {
+            Vec::new()
+        };
+        line.clear();
+        if file.read_until(delimiter, &mut line)? == 0 {
+            break;
+        }
+        ring.push_back(line);
+        if ring.len() > num_lines {
+            out.write_all(&ring[0])?;
+        }
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+// Same as all_but_last_lines, holding back the last `num_bytes` bytes.
+fn all_but_last_bytes(mut file: impl BufRead, num_bytes: usize, mut out: impl Write) -> MyResult<()> {
+    let mut ring: VecDeque<u8> = VecDeque::with_capacity(num_bytes.min(BUF_SIZE));
+    loop {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let len = chunk.len();
+        ring.extend(chunk);
+        file.consume(len);
+
+        // 超出K个的部分可以放心输出了
+        if ring.len() > num_bytes {
+            let excess = ring.len() - num_bytes;
+            let (front, back) = ring.as_slices();
+            if excess <= front.len() {
+                out.write_all(&front[..excess])?;
+            } else {
+                out.write_all(front)?;
+                out.write_all(&back[..excess - front.len()])?;
+            }
+            ring.drain(..excess);
+        }
     }
     Ok(())
 }
@@ -118,8 +290,7 @@ fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
 fn parse_positive_int(val: &str) -> MyResult<usize> {
     // 这个东西会给一个panic
     // unimplemented!();
-    // 这里会根据返回值就推断出是usize, 够远
-    match val.parse() {
+    match parse_count(val) {
         // 这里有个守卫guard if n > 0
         Ok(n) if n > 0 => Ok(n),
         // 直接给val，人要一个impl Error的Box所以会报错
@@ -127,6 +298,143 @@ fn parse_positive_int(val: &str) -> MyResult<usize> {
     }
 }
 
+// --------------------------------------------------
+// Like parse_positive_int, but zero is allowed
+fn parse_count(val: &str) -> MyResult<usize> {
+    // 数字后面可以跟GNU head的单位，比如1K, 4MiB, 2MB
+    let split = val.find(|c: char| !c.is_ascii_digit()).unwrap_or(val.len());
+    let (num, suffix) = val.split_at(split);
+    let multiplier = parse_suffix(suffix).ok_or(val)?;
+    // 这里会根据返回值就推断出是usize, 够远
+    match num.parse::<usize>().map(|n| n.checked_mul(multiplier)) {
+        Ok(Some(n)) => Ok(n),
+        _ => Err(From::from(val)),
+    }
+}
+
+// --------------------------------------------------
+// b = 512, K/KiB = 1024, KB = 1000, and so on up through E
+fn parse_suffix(suffix: &str) -> Option<usize> {
+    if suffix.is_empty() {
+        return Some(1);
+    }
+    if suffix == "b" {
+        return Some(512);
+    }
+
+    let mut chars = suffix.chars();
+    let power = match chars.next()? {
+        'k' | 'K' => 1,
+        'M' => 2,
+        'G' => 3,
+        'T' => 4,
+        'P' => 5,
+        'E' => 6,
+        _ => return None,
+    };
+    let base: usize = match chars.as_str() {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return None,
+    };
+    base.checked_pow(power)
+}
+
+// --------------------------------------------------
+// A UTF-8 character is a lead byte plus the continuation bytes after it. Bytes
+// that are not valid UTF-8 count as one character each, except stray
+// continuation bytes, which stay attached to the character before them.
+fn is_char_start(byte: u8) -> bool {
+    byte & 0xc0 != 0x80
+}
+
+// --------------------------------------------------
+fn first_chars(mut file: impl BufRead, num_chars: usize, mut out: impl Write) -> MyResult<()> {
+    let mut seen = 0;
+    loop {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+
+        // 找到第num_chars+1个字符开始的位置，前面的都输出
+        let end = chunk.iter().position(|&b| {
+            if is_char_start(b) {
+                seen += 1;
+            }
+            seen > num_chars
+        });
+        let len = end.unwrap_or(chunk.len());
+        out.write_all(&chunk[..len])?;
+        file.consume(len);
+        if end.is_some() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+fn all_but_last_chars(mut file: impl BufRead, num_chars: usize, mut out: impl Write) -> MyResult<()> {
+    let mut ring: VecDeque<u8> = VecDeque::new();
+    // ring里字符开头的个数
+    let mut starts = 0;
+    let mut buf = vec![];
+
+    // 从队头拿出一整个字符
+    let pop_char = |ring: &mut VecDeque<u8>, buf: &mut Vec<u8>| {
+        while let Some(b) = ring.pop_front() {
+            buf.push(b);
+            if is_char_start(b) {
+                break;
+            }
+        }
+        while ring.front().is_some_and(|&b| !is_char_start(b)) {
+            buf.push(ring.pop_front().unwrap());
+        }
+    };
+
+    loop {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        for &b in chunk {
+            ring.push_back(b);
+            if is_char_start(b) {
+                starts += 1;
+            }
+            // 后面还有一个字符开头，说明队头的字符已经完整了
+            if starts > num_chars + 1 {
+                pop_char(&mut ring, &mut buf);
+                starts -= 1;
+            }
+        }
+        let len = chunk.len();
+        file.consume(len);
+        out.write_all(&buf)?;
+        buf.clear();
+    }
+
+    while starts > num_chars {
+        pop_char(&mut ring, &mut buf);
+        starts -= 1;
+    }
+    out.write_all(&buf)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+// "K" takes the first K, "-K" everything but the last K. "-0" drops nothing,
+// so it is allowed like in GNU head.
+fn parse_take_value(val: &str) -> MyResult<TakeValue> {
+    match val.strip_prefix('-') {
+        Some(num) => parse_count(num).map(AllButLast),
+        None => parse_positive_int(val).map(First),
+    }
+    .map_err(|_| From::from(val))
+}
+
 // 仅针对某一个function的unit test
 #[test]
 fn test_parse_positive_int() {
@@ -144,5 +452,115 @@ fn test_parse_positive_int() {
     let res = parse_positive_int("0");
     assert!(res.is_err());
     assert_eq!(res.unwrap_err().to_string(), "0".to_string());
+
+    // GNU multiplier suffixes
+    assert_eq!(parse_positive_int("2b").unwrap(), 1024);
+    assert_eq!(parse_positive_int("1K").unwrap(), 1024);
+    assert_eq!(parse_positive_int("1k").unwrap(), 1024);
+    assert_eq!(parse_positive_int("1KiB").unwrap(), 1024);
+    assert_eq!(parse_positive_int("3KB").unwrap(), 3000);
+    assert_eq!(parse_positive_int("4MiB").unwrap(), 4 * 1024 * 1024);
+    assert_eq!(parse_positive_int("1GB").unwrap(), 1_000_000_000);
+
+    // Unknown suffixes, a bare suffix and overflow are errors
+    let res = parse_positive_int("1X");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "1X".to_string());
+    assert!(parse_positive_int("K").is_err());
+    assert!(parse_positive_int("1KiBB").is_err());
+    assert!(parse_positive_int("100000E").is_err());
+}
+
+#[test]
+fn test_parse_take_value() {
+    let res = parse_take_value("3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), First(3));
+
+    // A leading "-" means all but the last K
+    let res = parse_take_value("-3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), AllButLast(3));
+
+    // The whole value is reported on error
+    let res = parse_take_value("-foo");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "-foo".to_string());
+
+    let res = parse_take_value("--3");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "--3".to_string());
+
+    let res = parse_take_value("-0");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), AllButLast(0));
+
+    let res = parse_take_value("0");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "0".to_string());
 }
 
+#[test]
+fn test_all_but_last() {
+    let text = "one\ntwo\nthree\nfour";
+    let mut out = vec![];
+    assert!(all_but_last_lines(io::Cursor::new(text), 2, b'\n', &mut out).is_ok());
+    assert_eq!(out, b"one\ntwo\n");
+
+    let mut out = vec![];
+    assert!(all_but_last_lines(io::Cursor::new(text), 10, b'\n', &mut out).is_ok());
+    assert!(out.is_empty());
+
+    // With -z newlines are ordinary bytes
+    let mut out = vec![];
+    let nul = "a\nb\0c\0d";
+    assert!(all_but_last_lines(io::Cursor::new(nul), 1, b'\0', &mut out).is_ok());
+    assert_eq!(out, b"a\nb\0c\0");
+
+    let mut out = vec![];
+    assert!(all_but_last_bytes(io::Cursor::new(text), 6, &mut out).is_ok());
+    assert_eq!(out, b"one\ntwo\nthre");
+
+    // A tiny buffer forces the ring to wrap many times
+    let mut out = vec![];
+    let file = BufReader::with_capacity(3, io::Cursor::new(text));
+    assert!(all_but_last_bytes(file, 5, &mut out).is_ok());
+    assert_eq!(out, b"one\ntwo\nthree");
+}
+
+#[test]
+fn test_chars() {
+    // "Öne" is four bytes but three characters
+    let text = "Öne ☺ two\n";
+    let mut out = vec![];
+    assert!(first_chars(io::Cursor::new(text), 1, &mut out).is_ok());
+    assert_eq!(out, "Ö".as_bytes());
+
+    let mut out = vec![];
+    assert!(first_chars(io::Cursor::new(text), 5, &mut out).is_ok());
+    assert_eq!(out, "Öne ☺".as_bytes());
+
+    // A one-byte buffer splits every multibyte character
+    let mut out = vec![];
+    let file = BufReader::with_capacity(1, io::Cursor::new(text));
+    assert!(first_chars(file, 5, &mut out).is_ok());
+    assert_eq!(out, "Öne ☺".as_bytes());
+
+    let mut out = vec![];
+    assert!(all_but_last_chars(io::Cursor::new(text), 6, &mut out).is_ok());
+    assert_eq!(out, "Öne ".as_bytes());
+
+    let mut out = vec![];
+    let file = BufReader::with_capacity(1, io::Cursor::new(text));
+    assert!(all_but_last_chars(file, 1, &mut out).is_ok());
+    assert_eq!(out, "Öne ☺ two".as_bytes());
+
+    let mut out = vec![];
+    assert!(all_but_last_chars(io::Cursor::new(text), 100, &mut out).is_ok());
+    assert!(out.is_empty());
+
+    // Invalid bytes count as characters of their own
+    let mut out = vec![];
+    assert!(first_chars(io::Cursor::new(b"\xffab"), 2, &mut out).is_ok());
+    assert_eq!(out, b"\xffa");
+}
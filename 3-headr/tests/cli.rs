@@ -1,10 +1,12 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
-use std::io::prelude::*;
 use std::{
     error::Error,
-    fs::{self, File},
+    fs,
+    io::{Read, Write},
+    process::{Command as StdCommand, Stdio},
+    thread,
 };
 
 type TestResult = Result<(), Box<dyn Error>>;
@@ -15,6 +17,7 @@ const ONE: &str = "./tests/inputs/one.txt";
 const TWO: &str = "./tests/inputs/two.txt";
 const THREE: &str = "./tests/inputs/three.txt";
 const TEN: &str = "./tests/inputs/ten.txt";
+const NUL: &str = "./tests/inputs/nul.txt";
 
 // --------------------------------------------------
 fn random_string() -> String {
@@ -41,7 +44,7 @@ fn dies_bad_bytes() -> TestResult {
     let bad = random_string();
     let expected = format!("illegal byte count -- {}", &bad);
     Command::cargo_bin(PRG)?
-        .args(&["-c", &bad, EMPTY])
+        .args(["-c", &bad, EMPTY])
         .assert()
         .failure()
         .stderr(predicate::str::contains(expected));
@@ -55,7 +58,7 @@ fn dies_bad_lines() -> TestResult {
     let bad = random_string();
     let expected = format!("illegal line count -- {}", &bad);
     Command::cargo_bin(PRG)?
-        .args(&["-n", &bad, EMPTY])
+        .args(["-n", &bad, EMPTY])
         .assert()
         .failure()
         .stderr(predicate::str::contains(expected));
@@ -70,7 +73,7 @@ fn dies_bytes_and_lines() -> TestResult {
                used with '--bytes <BYTES>'";
 
     Command::cargo_bin(PRG)?
-        .args(&["-n", "1", "-c", "2"])
+        .args(["-n", "1", "-c", "2"])
         .assert()
         .failure()
         .stderr(predicate::str::contains(msg));
@@ -86,24 +89,49 @@ fn skips_bad_file() -> TestResult {
     Command::cargo_bin(PRG)?
         .args([EMPTY, &bad, ONE])
         .assert()
+        .failure()
+        .stdout(predicate::str::contains(format!("==> {} <==", ONE)))
         .stderr(predicate::str::is_match(expected)?);
 
     Ok(())
 }
 
+// --------------------------------------------------
+// stdout和stderr接到同一个地方，错误信息要排在前一个文件的内容后面
+#[test]
+#[cfg(unix)]
+fn bad_file_error_after_earlier_output() -> TestResult {
+    let bad = gen_bad_file();
+    let cmd = format!(
+        "{} {} {} {} 2>&1",
+        assert_cmd::cargo::cargo_bin(PRG).display(),
+        ONE,
+        bad,
+        TWO
+    );
+    let output = std::process::Command::new("sh")
+        .args(["-c", &cmd])
+        .output()?;
+    let combined = String::from_utf8(output.stdout)?;
+    let header = format!("==> {} <==", ONE);
+    let error = format!("Failed to open {}", bad);
+    let next = format!("==> {} <==", TWO);
+    let pos = |text: &str| combined.find(text).unwrap();
+    assert!(pos(&header) < pos(&error));
+    assert!(pos(&error) < pos(&next));
+    Ok(())
+}
+
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
-    // Extra work here due to lossy UTF
-    let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
+    // headr writes raw bytes, so compare bytes rather than lossy UTF-8
+    let expected = fs::read(expected_file)?;
 
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
         .success()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(expected));
 
     Ok(())
 }
@@ -114,18 +142,15 @@ fn run_stdin(
     input_file: &str,
     expected_file: &str,
 ) -> TestResult {
-    // Extra work here due to lossy UTF
-    let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
-    let input = fs::read_to_string(input_file)?;
+    // headr writes raw bytes, so compare bytes rather than lossy UTF-8
+    let expected = fs::read(expected_file)?;
+    let input = fs::read(input_file)?;
 
     Command::cargo_bin(PRG)?
         .write_stdin(input)
         .args(args)
         .assert()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(expected));
 
     Ok(())
 }
@@ -419,3 +444,283 @@ fn multiple_files_c4() -> TestResult {
         "tests/expected/all.c4.out",
     )
 }
+
+// --------------------------------------------------
+#[test]
+fn ten_n_minus3() -> TestResult {
+    run(&[TEN, "-n", "-3"], "tests/expected/ten.txt.n-3.out")
+}
+
+#[test]
+fn ten_n_minus20() -> TestResult {
+    run(&[TEN, "--lines=-20"], "tests/expected/ten.txt.n-20.out")
+}
+
+#[test]
+fn ten_c_minus5() -> TestResult {
+    run(&[TEN, "-c", "-5"], "tests/expected/ten.txt.c-5.out")
+}
+
+#[test]
+fn ten_n_minus0() -> TestResult {
+    // Dropping nothing prints the whole file
+    run(&[TEN, "-n", "-0"], TEN)
+}
+
+#[test]
+fn ten_c_minus0() -> TestResult {
+    run(&[TEN, "-c", "-0"], TEN)
+}
+
+#[test]
+fn ten_n_minus3_stdin() -> TestResult {
+    run_stdin(&["-n", "-3"], TEN, "tests/expected/ten.txt.n-3.out")
+}
+
+#[test]
+fn ten_c_minus5_stdin() -> TestResult {
+    run_stdin(&["-c", "-5"], TEN, "tests/expected/ten.txt.c-5.out")
+}
+
+#[test]
+fn multiple_files_n_minus1() -> TestResult {
+    run(
+        &["-n", "-1", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n-1.out",
+    )
+}
+
+#[test]
+fn multiple_files_c_minus2() -> TestResult {
+    run(
+        &["-c", "-2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.c-2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_c_1k() -> TestResult {
+    run(&[TEN, "-c", "1K"], "tests/expected/ten.txt.c1K.out")
+}
+
+#[test]
+fn ten_n_2b() -> TestResult {
+    run(&[TEN, "-n", "2b"], "tests/expected/ten.txt.n2b.out")
+}
+
+#[test]
+fn dies_bad_suffix() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1X", EMPTY])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("illegal byte count -- 1X"));
+
+    Ok(())
+}
+
+#[test]
+fn one_c1_raw_byte() -> TestResult {
+    // The first byte of "Ö" on its own, not a replacement character
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1", ONE])
+        .assert()
+        .success()
+        .stdout(predicate::eq(&b"\xc3"[..]));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn one_chars1() -> TestResult {
+    run(&[ONE, "--chars", "1"], "tests/expected/one.txt.chars1.out")
+}
+
+#[test]
+fn one_chars_minus3() -> TestResult {
+    run(&[ONE, "--chars=-3"], "tests/expected/one.txt.chars-3.out")
+}
+
+#[test]
+fn one_chars1_stdin() -> TestResult {
+    run_stdin(&["--chars", "1"], ONE, "tests/expected/one.txt.chars1.out")
+}
+
+#[test]
+fn dies_chars_and_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--chars", "1", "-c", "2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_quiet() -> TestResult {
+    run(&["-q", "-n", "2", ONE, TWO], "tests/expected/one-two.n2.q.out")
+}
+
+#[test]
+fn one_verbose() -> TestResult {
+    run(&["-v", "-n", "2", ONE], "tests/expected/one.txt.n2.v.out")
+}
+
+#[test]
+fn quiet_then_verbose() -> TestResult {
+    // The last of -q and -v wins, as in GNU head
+    run(
+        &["-q", "-v", "-n", "2", ONE, TWO],
+        "tests/expected/one-two.n2.qv.out",
+    )
+}
+
+#[test]
+fn nul_z_n2() -> TestResult {
+    run(&["-z", "-n", "2", NUL], "tests/expected/nul.txt.z.n2.out")
+}
+
+#[test]
+fn nul_z_n_minus3_stdin() -> TestResult {
+    run_stdin(&["-z", "-n", "-3"], NUL, "tests/expected/nul.txt.z.n-3.out")
+}
+
+// --------------------------------------------------
+// Feed `block` to headr's stdin over and over, `count` times or forever if
+// None, and hand back the child with stdout still piped.
+fn spawn_with_stream(
+    args: &[&str],
+    block: Vec<u8>,
+    count: Option<usize>,
+) -> Result<(std::process::Child, thread::JoinHandle<()>), Box<dyn Error>> {
+    let mut child = StdCommand::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || {
+        let mut written = 0;
+        // 写失败就是headr已经不读了，正常退出
+        while count.is_none_or(|n| written < n) {
+            if stdin.write_all(&block).is_err() {
+                break;
+            }
+            written += 1;
+        }
+    });
+    Ok((child, writer))
+}
+
+// --------------------------------------------------
+#[test]
+fn endless_stdin_stops_early() -> TestResult {
+    let (child, writer) = spawn_with_stream(&["-n", "3"], b"y\n".to_vec(), None)?;
+    let output = child.wait_with_output()?;
+    writer.join().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"y\ny\ny\n");
+    Ok(())
+}
+
+#[test]
+fn endless_stdin_stops_early_bytes() -> TestResult {
+    let (child, writer) = spawn_with_stream(&["-c", "1M"], b"0123456789abcdef".to_vec(), None)?;
+    let output = child.wait_with_output()?;
+    writer.join().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), 1024 * 1024);
+    Ok(())
+}
+
+#[test]
+fn closed_stdout_is_not_an_error() -> TestResult {
+    let (mut child, writer) = spawn_with_stream(&["-n", "1G"], b"y\n".to_vec(), None)?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buf = [0; 16];
+    stdout.read_exact(&mut buf)?;
+    drop(stdout);
+
+    let output = child.wait_with_output()?;
+    writer.join().unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    Ok(())
+}
+
+// --------------------------------------------------
+// Peak resident size of a running process in KiB, from /proc.
+#[cfg(target_os = "linux")]
+fn peak_rss_kib(pid: u32) -> Option<usize> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+// 64 MiB of distinct numbered lines: the output has to match exactly, and
+// halfway through it headr must not have grown anywhere near the input size.
+#[test]
+fn large_input_all_but_last() -> TestResult {
+    let input: Vec<u8> = (0..8 * 1024 * 1024)
+        .flat_map(|n| format!("{:07}\n", n).into_bytes())
+        .collect();
+    for (args, keep) in [
+        (["-c", "-1M"], input.len() - 1024 * 1024),
+        (["-n", "-100000"], input.len() - 100_000 * 8),
+    ] {
+        let expected = &input[..keep];
+        let (mut child, writer) = spawn_with_stream(&args, input.clone(), Some(1))?;
+        let mut stdout = child.stdout.take().unwrap();
+
+        let mut output = vec![0; keep / 2];
+        stdout.read_exact(&mut output)?;
+        #[cfg(target_os = "linux")]
+        if let Some(kib) = peak_rss_kib(child.id()) {
+            assert!(kib < 32 * 1024, "headr {:?} peaked at {} KiB", args, kib);
+        }
+        stdout.read_to_end(&mut output)?;
+
+        writer.join().unwrap();
+        assert!(child.wait()?.success());
+        assert_eq!(output.len(), expected.len());
+        assert!(output == expected, "headr {:?} output differs", args);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+// Pushes 4 GiB through the ring buffers, which only ever hold the last 1 MiB.
+// Slow, so run it with `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn huge_input_all_but_last() -> TestResult {
+    // 64 KiB of 32-byte lines
+    let block = b"0123456789abcdef0123456789abcde\n".repeat(2048);
+    let blocks = 64 * 1024;
+    let size = block.len() * blocks;
+    for (args, expected) in [
+        (["-c", "-1M"], size - 1024 * 1024),
+        (["-n", "-1M"], size - 32 * 1024 * 1024),
+    ] {
+        let (mut child, writer) = spawn_with_stream(&args, block.clone(), Some(blocks))?;
+        let mut stdout = child.stdout.take().unwrap();
+        let mut buf = vec![0; 64 * 1024];
+        let mut total = 0;
+        loop {
+            let n = stdout.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+        writer.join().unwrap();
+        assert!(child.wait()?.success());
+        assert_eq!(total, expected);
+    }
+    Ok(())
+}
@@ -1,10 +1,22 @@
 use clap::{App, Arg};
+use csv::WriterBuilder;
+use encoding_rs::{Decoder, Encoding, UTF_8};
+use serde_json::{json, Map, Value};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 use std::error::Error;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
+const BUF_SIZE: usize = 64 * 1024;
+// 单个文件至少要有两块这么大才会拆开并行数
+const CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
@@ -12,40 +24,433 @@ pub struct Config {
     words: bool,
     bytes: bool,
     chars: bool,
+    max_line_length: bool,
+    graphemes: bool,
+    unicode_words: bool,
+    threads: usize,
+    format: Format,
+    // None是默认的UTF-8，走自己的快速路径
+    encoding: Option<&'static Encoding>,
 }
 
+// --------------------------------------------------
+// How counts are written: GNU-style columns, or records for other programs
 #[derive(Debug, PartialEq)]
+enum Format {
+    Columns,
+    Json,
+    Csv,
+    Tsv,
+}
+
+#[derive(Debug, Default, PartialEq)]
 pub struct FileInfo {
     num_lines: usize,
     num_words: usize,
     num_bytes: usize,
     num_chars: usize,
+    // 最长一行的显示宽度，不算换行，tab按8列对齐
+    num_max_line_length: usize,
+    // UAX #29的扩展字素簇和单词
+    num_graphemes: usize,
+    num_unicode_words: usize,
+}
+
+impl FileInfo {
+    // 把另一份结果合进来：块合成文件，文件合成total
+    fn add(&mut self, other: &FileInfo) {
+        self.num_lines += other.num_lines;
+        self.num_words += other.num_words;
+        self.num_bytes += other.num_bytes;
+        self.num_chars += other.num_chars;
+        // 最长行取最大，不是相加
+        self.num_max_line_length = self.num_max_line_length.max(other.num_max_line_length);
+        self.num_graphemes += other.num_graphemes;
+        self.num_unicode_words += other.num_unicode_words;
+    }
+}
+
+// --------------------------------------------------
+// How much work a Counter does beyond counting lines and bytes
+#[derive(Clone, Copy, Debug)]
+struct Mode {
+    // 要不要把字节解成字符（单词、字符数、行宽都要）
+    decode: bool,
+    // 要不要按UAX #29切字素和单词，要攒一整行的文本
+    segment: bool,
+    // None是默认的UTF-8，走自己的快速路径
+    encoding: Option<&'static Encoding>,
+}
+
+impl Mode {
+    const ALL: Mode = Mode {
+        decode: true,
+        segment: true,
+        encoding: None,
+    };
+
+    fn new(config: &Config) -> Self {
+        let segment = config.graphemes || config.unicode_words;
+        Mode {
+            decode: segment || config.words || config.chars || config.max_line_length,
+            segment,
+            encoding: config.encoding,
+        }
+    }
 }
 
-pub fn count(mut file: impl BufRead) -> MyResult<FileInfo> {
-    let mut num_lines = 0;
-    let mut num_words = 0;
-    let mut num_bytes = 0;
-    let mut num_chars = 0;
-    let mut line = String::new();
+// --------------------------------------------------
+// Byte-level counting state. Bytes are fed in arbitrary slices, so a UTF-8
+// sequence or a word may straddle two calls to `update`. When only lines and
+// bytes are wanted (`mode.decode` is false) newlines are found with memchr
+// and nothing is decoded. Grapheme and UAX #29 word counts need the whole
+// line, so in that mode each line is kept as text until its newline; neither
+// kind of boundary ever spans a newline.
+//
+// Input does not have to be valid. Invalid UTF-8 is counted as if it had
+// been decoded with String::from_utf8_lossy: every maximal invalid sequence
+// is one character (U+FFFD), which is part of a word and one column wide.
+// Other encodings are decoded with encoding_rs, which replaces malformed
+// input the same way.
+struct Counter {
+    info: FileInfo,
+    mode: Mode,
+    // segment的时候攒着的当前行
+    line: String,
+    // 不是UTF-8的时候用这个解码，None就走下面自己的UTF-8解码
+    decoder: Option<Decoder>,
+    in_word: bool,
+    // 当前这行的显示宽度
+    line_len: usize,
+    // 最后读到的是不是换行（或者还什么都没读）
+    at_line_start: bool,
+    // 没读完的UTF-8字符：还差几个字节、目前的码点、下一个字节的合法范围
+    need: u32,
+    code: u32,
+    next: (u8, u8),
+}
+
+impl Counter {
+    fn new(mode: Mode) -> Self {
+        Counter {
+            info: FileInfo::default(),
+            mode,
+            line: String::new(),
+            decoder: mode.encoding.map(|e| e.new_decoder_without_bom_handling()),
+            in_word: false,
+            line_len: 0,
+            at_line_start: true,
+            need: 0,
+            code: 0,
+            next: (0x80, 0xbf),
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        self.info.num_bytes += buf.len();
+        if self.decoder.is_some() {
+            // UTF-16这些编码里换行不一定是一个0x0a字节，只能解码以后再数
+            self.decode(buf, false);
+            return;
+        }
+
+        self.at_line_start = buf.last() == Some(&b'\n');
+        self.info.num_lines += memchr::memchr_iter(b'\n', buf).count();
+        if !self.mode.decode {
+            return;
+        }
+
+        for &b in buf {
+            if self.need > 0 {
+                if (self.next.0..=self.next.1).contains(&b) {
+                    self.code = self.code << 6 | (b & 0x3f) as u32;
+                    self.need -= 1;
+                    self.next = (0x80, 0xbf);
+                    if self.need == 0 {
+                        let c = char::from_u32(self.code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                        self.push_char(c);
+                    }
+                    continue;
+                }
+                // 断掉的序列算一个替换字符，这个字节重新开始
+                self.need = 0;
+                self.push_char(char::REPLACEMENT_CHARACTER);
+            }
+
+            // 第二个字节的范围排除掉过长编码、代理项和超过U+10FFFF的
+            match b {
+                0x00..=0x7f => self.push_char(b as char),
+                0xc2..=0xdf => self.start_char(1, b & 0x1f, (0x80, 0xbf)),
+                0xe0 => self.start_char(2, b & 0x0f, (0xa0, 0xbf)),
+                0xed => self.start_char(2, b & 0x0f, (0x80, 0x9f)),
+                0xe1..=0xef => self.start_char(2, b & 0x0f, (0x80, 0xbf)),
+                0xf0 => self.start_char(3, b & 0x07, (0x90, 0xbf)),
+                0xf1..=0xf3 => self.start_char(3, b & 0x07, (0x80, 0xbf)),
+                0xf4 => self.start_char(3, b & 0x07, (0x80, 0x8f)),
+                _ => self.push_char(char::REPLACEMENT_CHARACTER),
+            }
+        }
+    }
+
+    fn start_char(&mut self, need: u32, bits: u8, next: (u8, u8)) {
+        self.need = need;
+        self.code = bits as u32;
+        self.next = next;
+    }
+
+    fn decode(&mut self, buf: &[u8], last: bool) {
+        let Some(mut decoder) = self.decoder.take() else {
+            return;
+        };
+        let len = decoder
+            .max_utf8_buffer_length(buf.len())
+            .unwrap_or(buf.len() * 3 + 16);
+        let mut text = String::with_capacity(len);
+        // 容量够大，一次就能把buf全部解完
+        let _ = decoder.decode_to_string(buf, &mut text, last);
+        self.decoder = Some(decoder);
+
+        for c in text.chars() {
+            if c == '\n' {
+                self.info.num_lines += 1;
+            }
+            self.push_char(c);
+        }
+        if let Some(c) = text.chars().last() {
+            self.at_line_start = c == '\n';
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        self.info.num_chars += 1;
+
+        if c.is_whitespace() {
+            self.in_word = false;
+        } else if !self.in_word {
+            self.info.num_words += 1;
+            self.in_word = true;
+        }
+
+        if self.mode.segment {
+            self.line.push(c);
+        }
+
+        match c {
+            '\n' => {
+                self.end_line();
+                self.line_len = 0;
+            }
+            // 和GNU一样，\r和\f回到行首
+            '\r' | '\x0c' => {
+                self.info.num_max_line_length = self.info.num_max_line_length.max(self.line_len);
+                self.line_len = 0;
+            }
+            // tab跳到下一个8的倍数
+            '\t' => self.line_len += 8 - self.line_len % 8,
+            // 宽字符占两列，控制字符和组合字符不占
+            _ => self.line_len += c.width().unwrap_or(0),
+        }
+    }
+
+    fn end_line(&mut self) {
+        self.info.num_max_line_length = self.info.num_max_line_length.max(self.line_len);
+
+        if self.mode.segment {
+            self.info.num_graphemes += self.line.graphemes(true).count();
+            self.info.num_unicode_words += self.line.unicode_words().count();
+            self.line.clear();
+        }
+    }
+
+    fn finish(mut self) -> FileInfo {
+        // 结尾没读完的字符也算一个
+        if self.need > 0 {
+            self.need = 0;
+            self.push_char(char::REPLACEMENT_CHARACTER);
+        }
+        self.decode(&[], true);
 
+        // 最后一行没有换行也算一行
+        if !self.at_line_start {
+            self.info.num_lines += 1;
+            if self.mode.decode || self.decoder.is_some() {
+                self.end_line();
+            }
+        }
+        self.info
+    }
+}
+
+// --------------------------------------------------
+fn feed(counter: &mut Counter, mut file: impl BufRead) -> io::Result<()> {
     loop {
-        let line_bytes = file.read_line(&mut line)?;
-        if line_bytes == 0 {
-            break;
-        }
-        num_bytes += line_bytes;
-        num_lines += 1;
-        num_words += line.split_whitespace().count();
-        num_chars += line.chars().count();
-        line.clear();
-    }
-
-    Ok(FileInfo {
-        num_lines,
-        num_words,
-        num_bytes,
-        num_chars,
+        let buf = file.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        counter.update(buf);
+        let len = buf.len();
+        file.consume(len);
+    }
+}
+
+// --------------------------------------------------
+pub fn count(file: impl BufRead) -> MyResult<FileInfo> {
+    Ok(count_all(file, Mode::ALL)?)
+}
+
+// --------------------------------------------------
+fn count_all(file: impl BufRead, mode: Mode) -> io::Result<FileInfo> {
+    let mut counter = Counter::new(mode);
+    feed(&mut counter, file)?;
+    Ok(counter.finish())
+}
+
+// --------------------------------------------------
+// Count the lines of `filename` that start inside `start..end`. A line that
+// crosses `end` is read to its newline here and skipped by the next chunk, so
+// every chunk begins on a line start and the chunks add up to the whole file.
+fn count_chunk(filename: &str, start: u64, end: u64, mode: Mode) -> io::Result<FileInfo> {
+    let mut file = BufReader::with_capacity(BUF_SIZE, File::open(filename)?);
+    let mut pos = start;
+    if start > 0 {
+        // 从前一个字节开始找，正好在行首的时候也不会多跳一行
+        file.seek(SeekFrom::Start(start - 1))?;
+        pos = start - 1 + file.skip_until(b'\n')? as u64;
+    }
+
+    let mut counter = Counter::new(mode);
+    if pos < end {
+        feed(&mut counter, (&mut file).take(end - pos))?;
+        // 把跨过end的那一行读完，一段一段地喂，行再长也不占内存
+        while !counter.at_line_start {
+            let buf = file.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let len = memchr::memchr(b'\n', buf).map_or(buf.len(), |i| i + 1);
+            counter.update(&buf[..len]);
+            file.consume(len);
+        }
+    }
+    Ok(counter.finish())
+}
+
+// --------------------------------------------------
+// One unit of work: whole inputs counted one after another, or a byte range
+// of a big regular file.
+struct Job {
+    files: Vec<usize>,
+    range: Option<(u64, u64)>,
+}
+
+// --------------------------------------------------
+// Split regular files of at least two CHUNK_SIZEs into up to `max_chunks`
+// ranges. Everything else (pipes, small files) is one job, except that every
+// `-` goes into a single job so stdin is read by one worker, in order: the
+// first `-` gets all of it and the later ones see EOF, like GNU wc.
+fn plan_jobs(files: &[String], max_chunks: usize) -> Vec<Job> {
+    let mut jobs: Vec<Job> = vec![];
+    let mut stdin_job: Option<usize> = None;
+    for (file, filename) in files.iter().enumerate() {
+        if filename == "-" {
+            match stdin_job {
+                Some(i) => jobs[i].files.push(file),
+                None => {
+                    stdin_job = Some(jobs.len());
+                    jobs.push(Job { files: vec![file], range: None });
+                }
+            }
+            continue;
+        }
+        let size = match fs::metadata(filename) {
+            Ok(meta) if meta.is_file() => meta.len(),
+            _ => 0,
+        };
+        let num_chunks = (size / CHUNK_SIZE).clamp(1, max_chunks as u64);
+        if num_chunks == 1 {
+            jobs.push(Job { files: vec![file], range: None });
+            continue;
+        }
+        for chunk in 0..num_chunks {
+            let start = size * chunk / num_chunks;
+            // 最后一块读到文件结尾为止
+            let end = if chunk + 1 == num_chunks {
+                u64::MAX
+            } else {
+                size * (chunk + 1) / num_chunks
+            };
+            jobs.push(Job { files: vec![file], range: Some((start, end)) });
+        }
+    }
+    jobs
+}
+
+// --------------------------------------------------
+// Count the files of `config` on up to `config.threads` worker threads.
+// `report` is called on this thread once per file, in the order given, as
+// soon as that file and every file before it are done.
+fn count_files(
+    config: &Config,
+    mut report: impl FnMut(&str, io::Result<FileInfo>) -> MyResult<()>,
+) -> MyResult<()> {
+    let files = &config.files;
+    let threads = config.threads;
+    let mode = Mode::new(config);
+    // 块是按换行字节切的，只有UTF-8才能这么切
+    let jobs = plan_jobs(files, if mode.encoding.is_some() { 1 } else { threads });
+    let mut pending: Vec<usize> = vec![0; files.len()];
+    for &file in jobs.iter().flat_map(|job| &job.files) {
+        pending[file] += 1;
+    }
+    let mut results: Vec<io::Result<FileInfo>> =
+        files.iter().map(|_| Ok(FileInfo::default())).collect();
+    let mut next_file = 0;
+    let next_job = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel::<(usize, io::Result<FileInfo>)>();
+        for _ in 0..threads.min(jobs.len()) {
+            let tx = tx.clone();
+            let (jobs, next_job) = (&jobs, &next_job);
+            scope.spawn(move || loop {
+                let i = next_job.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(i) else {
+                    break;
+                };
+                for &file in &job.files {
+                    let filename = &files[file];
+                    let res = match job.range {
+                        Some((start, end)) => count_chunk(filename, start, end, mode),
+                        None => open(filename).and_then(|file| count_all(file, mode)),
+                    };
+                    if tx.send((file, res)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        for (file, res) in rx {
+            // 同一个文件的几块合起来，有一块出错整个文件就算错
+            match (&mut results[file], res) {
+                (Ok(info), Ok(part)) => info.add(&part),
+                (Ok(_), Err(err)) => results[file] = Err(err),
+                (Err(_), _) => {}
+            }
+            pending[file] -= 1;
+            while next_file < files.len() && pending[next_file] == 0 {
+                let res = std::mem::replace(&mut results[next_file], Ok(FileInfo::default()));
+                // 输出出错就不数了，rx一扔worker也会停下
+                report(&files[next_file], res)?;
+                next_file += 1;
+            }
+        }
+        Ok(())
     })
 }
 
@@ -90,96 +495,391 @@ pub fn get_args() -> MyResult<Config> {
                 .help("Show line count")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("max_line_length")
+                .short("L")
+                .long("max-line-length")
+                .help("Show maximum line length")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("graphemes")
+                .long("graphemes")
+                .help("Show extended grapheme cluster count")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("unicode_words")
+                .long("unicode-words")
+                .help("Show word count using Unicode (UAX #29) word boundaries")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("files0_from")
+                .long("files0-from")
+                .value_name("F")
+                .help("Read input file names from F, separated by NULs; F of - means stdin")
+                .takes_value(true)
+                .conflicts_with("files"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Output format")
+                .possible_values(&["columns", "json", "csv", "tsv"])
+                .default_value("columns"),
+        )
+        .arg(
+            Arg::with_name("encoding")
+                .long("encoding")
+                .value_name("ENCODING")
+                .help("Decode input as ENCODING (e.g. latin1, utf-16le, shift_jis) [default: utf-8]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .value_name("N")
+                .help("Count with N threads [default: number of CPUs]")
+                .takes_value(true),
+        )
         .get_matches();
 
     let mut lines = matches.is_present("lines");
     let mut words = matches.is_present("words");
     let mut bytes = matches.is_present("bytes");
     let chars = matches.is_present("chars");
+    let max_line_length = matches.is_present("max_line_length");
+    let graphemes = matches.is_present("graphemes");
+    let unicode_words = matches.is_present("unicode_words");
 
-    // 这个是给默认值，如果4个全都是false， 则默认那3个是true
-    if [words, bytes, chars, lines].iter().all(|v| v == &false) {
+    // 这个是给默认值，如果7个全都是false， 则默认那3个是true
+    if [words, bytes, chars, lines, max_line_length, graphemes, unicode_words]
+        .iter()
+        .all(|v| v == &false)
+    {
         lines = true;
         words = true;
         bytes = true;
     }
 
+    let threads = match matches.value_of("threads") {
+        Some(val) => match val.parse() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("illegal thread count -- {}", val).into()),
+        },
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    let files = match matches.value_of("files0_from") {
+        Some(list) => read_files0(list)?,
+        None => matches.values_of_lossy("files").unwrap(),
+    };
+
+    let format = match matches.value_of("format") {
+        Some("json") => Format::Json,
+        Some("csv") => Format::Csv,
+        Some("tsv") => Format::Tsv,
+        _ => Format::Columns,
+    };
+
+    let encoding = match matches.value_of("encoding") {
+        Some(label) => match Encoding::for_label(label.as_bytes()) {
+            Some(encoding) if encoding == UTF_8 => None,
+            Some(encoding) => Some(encoding),
+            None => return Err(format!("unknown encoding -- {}", label).into()),
+        },
+        None => None,
+    };
+
     Ok(Config{
-        files: matches.values_of_lossy("files").unwrap(),
+        files,
         lines,
         words,
         bytes,
         chars,
+        max_line_length,
+        graphemes,
+        unicode_words,
+        threads,
+        format,
+        encoding,
     })
 }
 
-fn format_field(value: usize, show: bool) -> String {
-    if show {
-        format!("{:>8}", value)
-    } else {
-        "".to_string()
+// --------------------------------------------------
+// The NUL-separated file names of --files0-from, with GNU's checks: no empty
+// names, and no "-" when the list itself comes from stdin.
+fn read_files0(list: &str) -> MyResult<Vec<String>> {
+    let mut bytes = vec![];
+    open(list)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot open '{}' for reading: {}", list, e))?;
+
+    // 最后一个名字后面的NUL可有可无
+    if bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+    if bytes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut files = vec![];
+    for (i, name) in bytes.split(|&b| b == 0).enumerate() {
+        if name.is_empty() {
+            return Err(format!("{}:{}: invalid zero-length file name", list, i + 1).into());
+        }
+        if list == "-" && name == b"-" {
+            return Err(
+                "when reading file names from standard input, no file name of '-' allowed".into(),
+            );
+        }
+        let name = String::from_utf8(name.to_vec())
+            .map_err(|_| format!("{}:{}: file name is not valid UTF-8", list, i + 1))?;
+        files.push(name);
+    }
+    Ok(files)
+}
+
+fn format_field(value: usize, width: usize) -> String {
+    format!("{:>width$}", value, width = width)
+}
+
+// --------------------------------------------------
+// Names and values of the selected counts, in GNU order (lines, words,
+// chars, bytes, max line length) with the Unicode counts next to their
+// plain counterparts
+fn selected_counts(info: &FileInfo, config: &Config) -> Vec<(&'static str, usize)> {
+    [
+        ("lines", info.num_lines, config.lines),
+        ("words", info.num_words, config.words),
+        ("unicode_words", info.num_unicode_words, config.unicode_words),
+        ("chars", info.num_chars, config.chars),
+        ("graphemes", info.num_graphemes, config.graphemes),
+        ("bytes", info.num_bytes, config.bytes),
+        ("max_line_length", info.num_max_line_length, config.max_line_length),
+    ]
+    .into_iter()
+    .filter(|(_, _, show)| *show)
+    .map(|(name, value, _)| (name, value))
+    .collect()
+}
+
+// --------------------------------------------------
+// The selected counts, right-aligned to `width` and separated by single
+// spaces.
+fn format_counts(info: &FileInfo, config: &Config, width: usize) -> String {
+    selected_counts(info, config)
+        .into_iter()
+        .map(|(_, value)| format_field(value, width))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// --------------------------------------------------
+// Column width as GNU wc picks it, before anything is read: wide enough for
+// the combined size of the regular files, since no count can exceed the
+// byte total. Stdin and other non-regular inputs have unknown size, so they
+// get at least 7 columns. A single count for a single file is not padded.
+fn column_width(files: &[String], config: &Config) -> usize {
+    let num_fields = selected_counts(&FileInfo::default(), config).len();
+    if num_fields == 1 && files.len() == 1 {
+        return 1;
+    }
+
+    let mut min_width = 1;
+    let mut total_size = 0;
+    for filename in files {
+        match fs::metadata(filename) {
+            Ok(meta) if filename != "-" && meta.is_file() => total_size += meta.len(),
+            // 打不开的文件会在后面报错，不影响宽度
+            Err(_) if filename != "-" => {}
+            _ => min_width = 7,
+        }
     }
+    min_width.max(total_size.to_string().len())
 }
 
-pub fn run(config: Config) -> MyResult<()> {
+// 返回统计不了的文件数
+pub fn run(config: Config) -> MyResult<usize> {
     // println!("{:#?}", config);
-    let mut total_lines = 0;
-    let mut total_words = 0;
-    let mut total_bytes = 0;
-    let mut total_chars = 0;
-
-    for filename in &config.files {
-        match open(filename) {
-            Err(err) => eprintln!("{}: {}", filename, err),
-            Ok(file) => {
-                if let Ok(info) = count(file) {
-                    println!(
-                        "{}{}{}{}{}",
-                        format_field(info.num_lines, config.lines),
-                        format_field(info.num_words, config.words),
-                        format_field(info.num_bytes, config.bytes),
-                        format_field(info.num_chars, config.chars),
-                        if filename == "-" {
-                            "".to_string()
-                        } else {
-                            format!(" {}", filename)
-                        }
-                    );
-
-                    total_lines += info.num_lines;
-                    total_words += info.num_words;
-                    total_bytes += info.num_bytes;
-                    total_chars += info.num_chars;
+    let mut printer = Printer::new(&config);
+    let mut total = FileInfo::default();
+    let mut num_failed = 0;
+
+    count_files(&config, |filename, res| {
+        // 打开和统计的错误都报出来，接着处理下一个文件
+        match res {
+            Err(err) => {
+                eprintln!("{}: {}", filename, err);
+                num_failed += 1;
+            }
+            Ok(info) => {
+                printer.file(filename, &info)?;
+                total.add(&info);
+            }
+        }
+        Ok(())
+    })?;
+    printer.total(&total)?;
+
+    Ok(num_failed)
+}
+
+// --------------------------------------------------
+// Writes one record per file and then the total in the chosen format. The
+// structured formats always include the total; for CSV and TSV the first
+// column says whether a row is a "file" or the "total".
+struct Printer<'a> {
+    config: &'a Config,
+    width: usize,
+    num_records: usize,
+    // 只有CSV用csv crate，TSV自己转义
+    csv: Option<csv::Writer<io::Stdout>>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(config: &'a Config) -> Self {
+        Printer {
+            config,
+            width: column_width(&config.files, config),
+            num_records: 0,
+            csv: (config.format == Format::Csv)
+                .then(|| WriterBuilder::new().from_writer(io::stdout())),
+        }
+    }
+
+    fn file(&mut self, filename: &str, info: &FileInfo) -> MyResult<()> {
+        match self.config.format {
+            Format::Columns => println!(
+                "{}{}",
+                format_counts(info, self.config, self.width),
+                if filename == "-" {
+                    "".to_string()
+                } else {
+                    format!(" {}", filename)
                 }
+            ),
+            Format::Json => {
+                let mut record = Map::new();
+                record.insert("name".to_string(), json!(filename));
+                record.extend(json_counts(info, self.config));
+                print!("{}", if self.num_records == 0 { "{\"files\":[\n" } else { ",\n" });
+                serde_json::to_writer(io::stdout(), &record)?;
             }
+            Format::Csv | Format::Tsv => self.row("file", filename, info)?,
         }
+        self.num_records += 1;
+        Ok(())
     }
 
-    if config.files.len() > 1 {
-        println!(
-            "{}{}{}{} total",
-            format_field(total_lines, config.lines),
-            format_field(total_words, config.words),
-            format_field(total_bytes, config.bytes),
-            format_field(total_chars, config.chars),
-        );
+    fn total(&mut self, info: &FileInfo) -> MyResult<()> {
+        match self.config.format {
+            Format::Columns => {
+                if self.config.files.len() > 1 {
+                    println!("{} total", format_counts(info, self.config, self.width));
+                }
+            }
+            Format::Json => {
+                print!("{}],\"total\":", if self.num_records == 0 { "{\"files\":[" } else { "\n" });
+                serde_json::to_writer(io::stdout(), &json_counts(info, self.config))?;
+                println!("}}");
+            }
+            Format::Csv | Format::Tsv => {
+                self.row("total", "", info)?;
+                if let Some(csv) = &mut self.csv {
+                    csv.flush()?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    Ok(())
+    fn row(&mut self, kind: &str, name: &str, info: &FileInfo) -> MyResult<()> {
+        let counts = selected_counts(info, self.config);
+        let names = counts.iter().map(|(name, _)| name.to_string());
+        let header: Vec<String> = ["type", "name"]
+            .map(String::from)
+            .into_iter()
+            .chain(names)
+            .collect();
+        let values = counts.iter().map(|(_, value)| value.to_string());
+        let record: Vec<String> = [kind.to_string(), name.to_string()]
+            .into_iter()
+            .chain(values)
+            .collect();
+        // 第一行前面先写表头
+        let first = self.num_records == 0;
+        match &mut self.csv {
+            Some(csv) => {
+                if first {
+                    csv.write_record(&header)?;
+                }
+                csv.write_record(&record)?;
+            }
+            None => {
+                let tsv = |fields: &[String]| {
+                    fields
+                        .iter()
+                        .map(|f| escape_tsv(f))
+                        .collect::<Vec<_>>()
+                        .join("\t")
+                };
+                if first {
+                    println!("{}", tsv(&header));
+                }
+                println!("{}", tsv(&record));
+            }
+        }
+        Ok(())
+    }
 }
 
-pub fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
+// --------------------------------------------------
+// TSV has no quoting, so tabs, newlines, carriage returns and backslashes in
+// a field are written as \t, \n, \r and \\.
+fn escape_tsv(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\\' => escaped.push_str("\\\\"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// --------------------------------------------------
+fn json_counts(info: &FileInfo, config: &Config) -> Map<String, Value> {
+    selected_counts(info, config)
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), json!(value)))
+        .collect()
+}
+
+pub fn open(filename: &str) -> io::Result<Box<dyn BufRead + Send>> {
     match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+        "-" => Ok(Box::new(BufReader::with_capacity(BUF_SIZE, io::stdin()))),
+        _ => Ok(Box::new(BufReader::with_capacity(BUF_SIZE, File::open(filename)?))),
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::{count, format_field, FileInfo};
-    use std::io::Cursor;
+    use super::{count, count_all, count_chunk, format_field, FileInfo, Mode};
+    use rand::Rng;
+    use std::io::{BufReader, Cursor, Write};
+
+    const LINES_ONLY: Mode = Mode {
+        decode: false,
+        segment: false,
+        encoding: None,
+    };
 
     #[test]
     fn test_count() {
@@ -191,15 +891,155 @@ mod tests {
             num_words: 10,
             num_chars: 48,
             num_bytes: 48,
+            num_max_line_length: 46,
+            num_graphemes: 47,
+            num_unicode_words: 10,
         };
         assert_eq!(info.unwrap(), expected);
     }
 
     #[test]
     fn test_format_field() {
-        assert_eq!(format_field(1, false), "");
-        assert_eq!(format_field(3, true), "       3");
-        assert_eq!(format_field(10, true), "      10");
+        assert_eq!(format_field(3, 8), "       3");
+        assert_eq!(format_field(10, 8), "      10");
+        assert_eq!(format_field(10, 1), "10");
+        assert_eq!(format_field(10, 3), " 10");
+    }
+
+    fn line_length(text: &str) -> usize {
+        count(Cursor::new(text)).unwrap().num_max_line_length
+    }
+
+    #[test]
+    fn test_line_length() {
+        assert_eq!(line_length(""), 0);
+        assert_eq!(line_length("abc\n"), 3);
+        assert_eq!(line_length("abc\r\n"), 3);
+        assert_eq!(line_length("abc\r"), 3);
+        assert_eq!(line_length("Öne"), 3);
+        assert_eq!(line_length("\tx"), 9);
+        assert_eq!(line_length("abc\tx\n"), 9);
+        assert_eq!(line_length("12345678\tx"), 17);
+        assert_eq!(line_length("ab\nabcd\na\n"), 4);
+        assert_eq!(line_length("ab\rabcd\n"), 4);
+        assert_eq!(line_length("abcd\rab\n"), 4);
+        assert_eq!(line_length("日本語\n"), 6);
+        assert_eq!(line_length("ｗｉｄｅ x\n"), 10);
+        assert_eq!(line_length("e\u{301}\u{301}\n"), 1);
+        assert_eq!(line_length("a\u{1}\u{7f}b\u{200b}\n"), 2);
     }
-}
 
+    #[test]
+    fn test_count_split_input() {
+        // Multibyte characters and words cut across buffer refills
+        let text = "Öne ☺ line\n  two  words\nno newline";
+        let whole = count(Cursor::new(text)).unwrap();
+        assert_eq!(whole.num_lines, 3);
+        assert_eq!(whole.num_words, 7);
+        assert_eq!(whole.num_chars, 34);
+        for capacity in 1..5 {
+            let file = BufReader::with_capacity(capacity, Cursor::new(text));
+            assert_eq!(count(file).unwrap(), whole);
+        }
+
+        // Lines and bytes alone don't need decoding
+        let info = count_all(Cursor::new(text), LINES_ONLY).unwrap();
+        assert_eq!((info.num_lines, info.num_bytes), (3, whole.num_bytes));
+    }
+
+    #[test]
+    fn test_count_invalid_utf8() {
+        // Each maximal invalid sequence is one non-space character
+        let info = count(Cursor::new(b"ab\xffcd \xe2\x98 x\xc3")).unwrap();
+        assert_eq!(info.num_bytes, 11);
+        assert_eq!(info.num_chars, 10);
+        assert_eq!(info.num_words, 3);
+        assert_eq!(info.num_max_line_length, 10);
+
+        // Overlong forms and surrogates are invalid too
+        for bad in [&b"\xc0\x80"[..], b"\xe0\x80\x80", b"\xed\xa0\x80", b"\xf4\x90\x80\x80"] {
+            let lossy = String::from_utf8_lossy(bad);
+            assert_eq!(count(Cursor::new(bad)).unwrap().num_chars, lossy.chars().count());
+        }
+    }
+
+    #[test]
+    fn test_count_matches_lossy() {
+        // Random bytes, heavy on UTF-8 lead and continuation bytes, counted
+        // through a small buffer must match the lossily decoded text
+        let alphabet = b"a \n\t\x80\x8f\x90\xa0\xbf\xc2\xc3\xdf\xe0\xe2\xed\xef\xf0\xf4\xf5\xff";
+        let mut rng = rand::thread_rng();
+        for _ in 0..500 {
+            let len = rng.gen_range(0..40);
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                .collect();
+            let lossy = String::from_utf8_lossy(&bytes);
+            let file = BufReader::with_capacity(3, Cursor::new(&bytes));
+            let info = count(file).unwrap();
+            assert_eq!(info.num_chars, lossy.chars().count(), "{:?}", bytes);
+            assert_eq!(info.num_words, lossy.split_whitespace().count(), "{:?}", bytes);
+            assert_eq!(info.num_bytes, bytes.len());
+        }
+    }
+
+    #[test]
+    fn test_count_encodings() {
+        let latin1 = encoding_rs::Encoding::for_label(b"latin1");
+        let info = count_all(Cursor::new(b"caf\xe9 cr\xe8me\n"), Mode { encoding: latin1, ..Mode::ALL }).unwrap();
+        assert_eq!((info.num_lines, info.num_words, info.num_chars, info.num_bytes), (1, 2, 11, 11));
+
+        // In UTF-16 a newline is two bytes and 0x0a alone is not a line end
+        let text: Vec<u8> = "Öne\n\u{10a}two\nthree"
+            .encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+        for capacity in [1, 3, 64] {
+            let file = BufReader::with_capacity(capacity, Cursor::new(&text));
+            let info = count_all(file, Mode { encoding: Some(encoding_rs::UTF_16LE), ..LINES_ONLY }).unwrap();
+            assert_eq!(info.num_lines, 3);
+            assert_eq!(info.num_words, 3);
+            assert_eq!(info.num_chars, 14);
+            assert_eq!(info.num_bytes, 28);
+            assert_eq!(info.num_max_line_length, 5);
+        }
+    }
+
+    #[test]
+    fn test_count_unicode() {
+        // é as e + combining accent, a flag, a family emoji and CJK text
+        let text = "cafe\u{301} 🇳🇴 👨\u{200d}👩\u{200d}👧 中文字符, hello-world\r\n";
+        let file = BufReader::with_capacity(2, Cursor::new(text));
+        let info = count(file).unwrap();
+        assert_eq!(info.num_chars, 34);
+        assert_eq!(info.num_graphemes, 27);
+        assert_eq!(info.num_words, 5);
+        assert_eq!(info.num_unicode_words, 7);
+
+        // Lines without a final newline are segmented too
+        let info = count(Cursor::new("a\u{301}b\n\nc d")).unwrap();
+        assert_eq!(info.num_graphemes, 7);
+        assert_eq!(info.num_unicode_words, 3);
+    }
+
+    #[test]
+    fn test_count_chunk() {
+        let text = "one two\nthree\n\nÖne\tfour fi\u{301}ve\r\nsix\nseven";
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        let filename = file.path().to_str().unwrap();
+        let whole = count(Cursor::new(text)).unwrap();
+
+        // Every way of cutting the file in two or three adds up to the same
+        let size = text.len() as u64;
+        for a in 0..=size {
+            for b in a..=size {
+                let mut info = FileInfo::default();
+                for (start, end) in [(0, a), (a, b), (b, u64::MAX)] {
+                    info.add(&count_chunk(filename, start, end, Mode::ALL).unwrap());
+                }
+                assert_eq!(info, whole, "cut at {} and {}", a, b);
+            }
+        }
+    }
+}
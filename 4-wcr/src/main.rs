@@ -1,6 +1,10 @@
 fn main() {
-    if let Err(e) = wcr::get_args().and_then(wcr::run) {
-        eprintln!("{}",e);
-        std::process::exit(1);
+    match wcr::get_args().and_then(wcr::run) {
+        Ok(0) => {}
+        Ok(_) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -2,6 +2,8 @@ use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
 use std::fs;
+use std::io::Write;
+use tempfile::NamedTempFile;
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -9,6 +11,11 @@ const PRG: &str = "wcr";
 const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const ATLAMAL: &str = "tests/inputs/atlamal.txt";
+const FILES0: &str = "tests/inputs/files0.txt";
+const LATIN1: &str = "tests/inputs/latin1.txt";
+const UTF16LE: &str = "tests/inputs/utf16le.txt";
+const UNICODE: &str = "tests/inputs/unicode.txt";
+const WIDE: &str = "tests/inputs/wide.txt";
 
 // --------------------------------------------------
 fn gen_bad_file() -> String {
@@ -29,7 +36,7 @@ fn gen_bad_file() -> String {
 #[test]
 fn dies_chars_and_bytes() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&["-m", "-c"])
+        .args(["-m", "-c"])
         .assert()
         .failure()
         .stderr(predicate::str::contains(
@@ -57,11 +64,23 @@ fn skips_bad_file() -> TestResult {
     Command::cargo_bin(PRG)?
         .arg(bad)
         .assert()
-        .success()
+        .failure()
         .stderr(predicate::str::is_match(expected)?);
     Ok(())
 }
 
+// --------------------------------------------------
+#[test]
+fn directory_is_an_error() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", FOX])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(format!("      1       9      48 {}\n", FOX)))
+        .stderr(predicate::str::contains("tests/inputs: Is a directory"));
+    Ok(())
+}
+
 // --------------------------------------------------
 #[test]
 fn empty() -> TestResult {
@@ -212,3 +231,364 @@ fn test_all_words_lines() -> TestResult {
 fn test_all_bytes_lines() -> TestResult {
     run(&["-cl", EMPTY, FOX, ATLAMAL], "tests/expected/all.cl.out")
 }
+
+// --------------------------------------------------
+#[test]
+fn fox_max_line_length() -> TestResult {
+    run(&["-L", FOX], "tests/expected/fox.txt.L.out")
+}
+
+#[test]
+fn fox_lines_max_line_length() -> TestResult {
+    run(&["-l", "--max-line-length", FOX], "tests/expected/fox.txt.lL.out")
+}
+
+#[test]
+fn atlamal_max_line_length() -> TestResult {
+    run(&["-L", ATLAMAL], "tests/expected/atlamal.txt.L.out")
+}
+
+#[test]
+fn empty_lines_max_line_length() -> TestResult {
+    run(&["-lL", EMPTY], "tests/expected/empty.txt.lL.out")
+}
+
+#[test]
+fn wide_max_line_length() -> TestResult {
+    run(&["-L", WIDE], "tests/expected/wide.txt.L.out")
+}
+
+#[test]
+fn wide_lines_words_chars_max_line_length() -> TestResult {
+    run(&["-lwmL", WIDE], "tests/expected/wide.txt.lwmL.out")
+}
+
+#[test]
+fn test_all_max_line_length() -> TestResult {
+    run(&["-L", EMPTY, FOX, ATLAMAL], "tests/expected/all.L.out")
+}
+
+#[test]
+fn test_all_lines_words_bytes_max_line_length() -> TestResult {
+    run(&["-lwcL", EMPTY, FOX, ATLAMAL], "tests/expected/all.lwcL.out")
+}
+
+#[test]
+fn atlamal_stdin_max_line_length() -> TestResult {
+    let input = fs::read_to_string(ATLAMAL)?;
+    let expected =
+        fs::read_to_string("tests/expected/atlamal.txt.stdin.L.out")?;
+    Command::cargo_bin(PRG)?
+        .arg("-L")
+        .write_stdin(input)
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn stdin_with_files_is_wide() -> TestResult {
+    // Stdin has no known size, so every column gets at least 7 places
+    let input = fs::read_to_string(FOX)?;
+    Command::cargo_bin(PRG)?
+        .args(["-lw", "-", EMPTY])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(format!(
+            "      1       9\n      0       0 {}\n      1       9 total\n",
+            EMPTY
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn test_all_threads() -> TestResult {
+    run(&["--threads", "3", EMPTY, FOX, ATLAMAL], "tests/expected/all.out")
+}
+
+#[test]
+fn dies_bad_threads() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--threads", "0", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("illegal thread count -- 0"));
+    Ok(())
+}
+
+#[test]
+fn repeated_stdin_threads() -> TestResult {
+    // The first - reads all of stdin and the second one sees EOF, even when
+    // there are workers to spare
+    Command::cargo_bin(PRG)?
+        .args(["--threads", "4", "-", "-"])
+        .write_stdin("one two\n".repeat(200_000))
+        .assert()
+        .success()
+        .stdout(concat!(
+            " 200000  400000 1600000\n",
+            "      0       0       0\n",
+            " 200000  400000 1600000 total\n",
+        ));
+    Ok(())
+}
+
+#[test]
+fn big_file_chunks_match_sequential() -> TestResult {
+    // Big enough to be split into chunks, with lines and multibyte
+    // characters falling across every chunk boundary
+    let text = fs::read_to_string(ATLAMAL)?;
+    let mut file = NamedTempFile::new()?;
+    file.write_all(text.repeat(48 * 1024 * 1024 / text.len()).as_bytes())?;
+    let filename = file.path().to_str().unwrap();
+
+    let flags = ["-lwmL", "-lc"];
+    for flag in flags {
+        let expected = Command::cargo_bin(PRG)?
+            .args([flag, "--threads", "1", filename])
+            .output()?;
+        assert!(expected.status.success());
+        Command::cargo_bin(PRG)?
+            .args([flag, "--threads", "3", filename])
+            .assert()
+            .success()
+            .stdout(expected.stdout);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files0_from() -> TestResult {
+    run(&["--files0-from", FILES0], "tests/expected/all.out")
+}
+
+#[test]
+fn files0_from_stdin() -> TestResult {
+    let input = fs::read(FILES0)?;
+    let expected = fs::read_to_string("tests/expected/all.out")?;
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", "-"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn files0_from_and_files() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", FILES0, FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn files0_from_empty_name() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", "-"])
+        .write_stdin(format!("{}\0\0{}", FOX, EMPTY))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("-:2: invalid zero-length file name"));
+    Ok(())
+}
+
+#[test]
+fn files0_from_bad_file() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", &bad])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!("cannot open '{}' for reading", bad)));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn test_all_json() -> TestResult {
+    run(&["--format", "json", EMPTY, FOX, ATLAMAL], "tests/expected/all.json.out")
+}
+
+#[test]
+fn test_all_csv() -> TestResult {
+    run(
+        &["--format", "csv", "-lwmL", EMPTY, FOX, ATLAMAL],
+        "tests/expected/all.lwmL.csv.out",
+    )
+}
+
+#[test]
+fn test_all_tsv() -> TestResult {
+    run(&["--format=tsv", EMPTY, FOX, ATLAMAL], "tests/expected/all.tsv.out")
+}
+
+#[test]
+fn json_stdin_with_total() -> TestResult {
+    // The total is always there, even for a single input
+    let input = fs::read_to_string(FOX)?;
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", "-l"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("{\"files\":[\n{\"name\":\"-\",\"lines\":1}\n],\"total\":{\"lines\":1}}\n");
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn json_escapes_names() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("say \"hi\"\t.txt");
+    fs::copy(FOX, &path)?;
+    let filename = path.to_str().unwrap();
+    let escaped = filename.replace('"', "\\\"").replace('\t', "\\t");
+
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", "-c", filename])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{{\"files\":[\n{{\"name\":\"{}\",\"bytes\":48}}\n],\"total\":{{\"bytes\":48}}}}\n",
+            escaped
+        ));
+    Ok(())
+}
+
+#[test]
+fn csv_quotes_names() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("fox, copy.txt");
+    fs::copy(FOX, &path)?;
+    let filename = path.to_str().unwrap();
+
+    Command::cargo_bin(PRG)?
+        .args(["--format", "csv", "-c", filename])
+        .assert()
+        .success()
+        .stdout(format!("type,name,bytes\nfile,\"{}\",48\ntotal,,48\n", filename));
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn tsv_escapes_names() -> TestResult {
+    // TSV has no quotes; a tab, newline or backslash in a name is escaped
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("fox\t\"copy\"\n\\.txt");
+    fs::copy(FOX, &path)?;
+    let filename = path.to_str().unwrap();
+    let escaped = filename
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--format", "tsv", "-c", filename])
+        .assert()
+        .success()
+        .stdout(format!("type\tname\tbytes\nfile\t{}\t48\ntotal\t\t48\n", escaped));
+    Ok(())
+}
+
+#[test]
+fn dies_bad_format() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "xml", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("isn't a valid value for '--format"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn invalid_utf8_is_counted() -> TestResult {
+    run(&[LATIN1], "tests/expected/latin1.txt.out")
+}
+
+#[test]
+fn invalid_utf8_chars() -> TestResult {
+    // Each invalid sequence is one character, so "\xe2\x82" counts once
+    run(&["-lwmL", LATIN1], "tests/expected/latin1.txt.lwmL.out")
+}
+
+#[test]
+fn latin1_chars() -> TestResult {
+    run(
+        &["-lwmL", "--encoding", "latin1", LATIN1],
+        "tests/expected/latin1.txt.latin1.lwmL.out",
+    )
+}
+
+#[test]
+fn utf16le_chars() -> TestResult {
+    run(
+        &["-lwmL", "--encoding", "utf-16le", UTF16LE],
+        "tests/expected/utf16le.txt.lwmL.out",
+    )
+}
+
+#[test]
+fn utf16le_lines_bytes() -> TestResult {
+    run(
+        &["-lc", "--encoding=UTF-16LE", UTF16LE],
+        "tests/expected/utf16le.txt.lc.out",
+    )
+}
+
+#[test]
+fn dies_bad_encoding() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "klingon", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown encoding -- klingon"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn unicode_graphemes() -> TestResult {
+    run(&["--graphemes", UNICODE], "tests/expected/unicode.txt.graphemes.out")
+}
+
+#[test]
+fn unicode_words() -> TestResult {
+    run(&["--unicode-words", UNICODE], "tests/expected/unicode.txt.unicode-words.out")
+}
+
+#[test]
+fn unicode_all_counts() -> TestResult {
+    run(
+        &["-wm", "--graphemes", "--unicode-words", UNICODE],
+        "tests/expected/unicode.txt.wm.graphemes.unicode-words.out",
+    )
+}
+
+#[test]
+fn unicode_with_total() -> TestResult {
+    run(
+        &["--graphemes", "--unicode-words", UNICODE, FOX],
+        "tests/expected/unicode-fox.graphemes.unicode-words.out",
+    )
+}
+
+#[test]
+fn unicode_json_names() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", "--graphemes", "--unicode-words", FOX])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "\"unicode_words\":9,\"graphemes\":48}",
+        ));
+    Ok(())
+}
@@ -1,17 +1,112 @@
 use clap::{App, Arg};
+use serde_json::json;
 use std::{
+    borrow::Cow,
+    cmp::{Ordering, Reverse},
+    collections::{hash_map::RandomState, BinaryHeap, HashMap},
     error::Error,
-    fs::File,
-    io::{self, BufRead, BufReader, Write},
+    fs::{self, File},
+    hash::BuildHasher,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str,
 };
+use tempfile::{NamedTempFile, TempPath};
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
 #[derive(Debug)]
 pub struct Config {
-    in_file: String,
+    // 多个输入文件按顺序接起来当成一个流
+    in_files: Vec<String>,
     out_file: Option<String>,
     count: bool,
+    // -d只要重复的，-u只要不重复的，两个都给就什么都不输出
+    repeated: bool,
+    unique: bool,
+    // -D，重复的每一行都输出
+    all_repeated: Option<Delimit>,
+    key: KeySpec,
+    // --global：不相邻的重复也去掉，按第一次出现的顺序输出
+    global: bool,
+    // --top N：出现最多的N行和次数
+    top: Option<usize>,
+    // 计数表超过这么多字节就写到临时文件里
+    memory_limit: Option<usize>,
+    format: Format,
+}
+
+impl Config {
+    // -d只要重复的，-u只要不重复的
+    fn wanted(&self, count: u64) -> bool {
+        match (self.repeated, self.unique) {
+            (true, true) => false,
+            (true, false) => count > 1,
+            (false, true) => count == 1,
+            (false, false) => true,
+        }
+    }
+}
+
+// --all-repeated=METHOD: how groups of duplicates are set apart
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Delimit {
+    None,
+    Prepend,
+    Separate,
+}
+
+// --------------------------------------------------
+// How groups are written: like uniq, or one record per group for other
+// programs
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Format {
+    Text,
+    Json,
+    Tsv,
+}
+
+// --------------------------------------------------
+// Which part of a line is compared with its neighbour. Lines are equal
+// when their keys are equal. Everything is in bytes, like GNU uniq.
+#[derive(Debug, Default, PartialEq)]
+struct KeySpec {
+    // 行的结束符，-z的时候是NUL，不算在key里
+    delimiter: u8,
+    skip_fields: usize,
+    skip_chars: usize,
+    check_chars: Option<usize>,
+    ignore_case: bool,
+}
+
+impl KeySpec {
+    // 顺序和GNU一样：去掉结束符，跳过字段，再跳过字节，最后截取-w个字节
+    fn key<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut rest = line.strip_suffix(&[self.delimiter]).unwrap_or(line);
+
+        // 字段是一串空白加上一串非空白
+        for _ in 0..self.skip_fields {
+            let blanks = rest.iter().take_while(|&&b| is_blank(b)).count();
+            rest = &rest[blanks..];
+            let field = rest.iter().take_while(|&&b| !is_blank(b)).count();
+            rest = &rest[field..];
+        }
+
+        rest = &rest[self.skip_chars.min(rest.len())..];
+        if let Some(n) = self.check_chars {
+            rest = &rest[..n.min(rest.len())];
+        }
+
+        if self.ignore_case {
+            Cow::Owned(rest.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(rest)
+        }
+    }
+}
+
+fn is_blank(b: u8) -> bool {
+    b == b' ' || b == b'\t'
 }
 pub fn get_args() -> MyResult<Config> {
     let matches = App::new("uniqr")
@@ -19,15 +114,19 @@ pub fn get_args() -> MyResult<Config> {
             .author("Ken Youens-Clark <kyclark@gmail.com>")
             .about("Rust uniq")
             .arg(
-                Arg::with_name("in_file")
-                    .value_name("IN_FILE")
-                    .help("Input file")
+                Arg::with_name("files")
+                    .value_name("FILE")
+                    .help("Input file(s); without --output a second FILE is the output")
+                    .multiple(true)
                     .default_value("-"),
             )
             .arg(
-                Arg::with_name("out_file")
+                Arg::with_name("output")
+                    .short("o")
+                    .long("output")
                     .value_name("OUT_FILE")
-                    .help("Output file"),
+                    .help("Output file; every FILE is then an input")
+                    .takes_value(true),
             )
             .arg(
                 Arg::with_name("count")
@@ -36,66 +135,805 @@ pub fn get_args() -> MyResult<Config> {
                     .help("Show counts")
                     .takes_value(false),
             )
+            .arg(
+                Arg::with_name("repeated")
+                    .short("d")
+                    .long("repeated")
+                    .help("Only print duplicate lines, one for each group")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("all_repeated")
+                    .short("D")
+                    .long("all-repeated")
+                    .value_name("METHOD")
+                    .help("Print all duplicate lines, groups delimited by METHOD")
+                    .possible_values(&["none", "prepend", "separate"])
+                    .min_values(0)
+                    .max_values(1)
+                    .require_equals(true)
+                    .conflicts_with("count"),
+            )
+            .arg(
+                Arg::with_name("unique")
+                    .short("u")
+                    .long("unique")
+                    .help("Only print unique lines")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("ignore_case")
+                    .short("i")
+                    .long("ignore-case")
+                    .help("Ignore differences in case when comparing")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("skip_fields")
+                    .short("f")
+                    .long("skip-fields")
+                    .value_name("N")
+                    .help("Avoid comparing the first N fields")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("skip_chars")
+                    .short("s")
+                    .long("skip-chars")
+                    .value_name("N")
+                    .help("Avoid comparing the first N bytes")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("check_chars")
+                    .short("w")
+                    .long("check-chars")
+                    .value_name("N")
+                    .help("Compare no more than N bytes in lines")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("zero_terminated")
+                    .short("z")
+                    .long("zero-terminated")
+                    .help("Line delimiter is NUL, not newline")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name("global")
+                    .short("g")
+                    .long("global")
+                    .help("Remove duplicates anywhere in the input, not just adjacent ones")
+                    .takes_value(false)
+                    .conflicts_with("all_repeated"),
+            )
+            .arg(
+                Arg::with_name("top")
+                    .long("top")
+                    .value_name("N")
+                    .help("Print the N most frequent lines with their counts")
+                    .takes_value(true)
+                    .conflicts_with_all(&["all_repeated", "repeated", "unique"]),
+            )
+            .arg(
+                Arg::with_name("memory_limit")
+                    .long("memory-limit")
+                    .value_name("SIZE")
+                    .help("With --global or --top, spill counts to disk above SIZE (e.g. 512M)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .help("Output format; json and tsv give each group's count and first and last line, json lists lines that aren't UTF-8 as \"bytes\" [default: text]")
+                    .possible_values(&["text", "json", "tsv"])
+                    .conflicts_with("all_repeated"),
+            )
             .get_matches();
 
+    let skip_fields = parse_count(matches.value_of("skip_fields"), "fields to skip")?;
+    let skip_chars = parse_count(matches.value_of("skip_chars"), "bytes to skip")?;
+    let check_chars = matches
+        .value_of("check_chars")
+        .map(|val| parse_count(Some(val), "bytes to compare"))
+        .transpose()?;
+
+    let top = matches
+        .value_of("top")
+        .map(|val| parse_count(Some(val), "lines to print"))
+        .transpose()?;
+    let memory_limit = matches
+        .value_of("memory_limit")
+        .map(parse_size)
+        .transpose()?;
+    // 只有--global和--top会把计数放在内存里，别的模式给了也没用。
+    // clap 2没有requires_any，放进ArgGroup又会把--top的冲突传给--global
+    if memory_limit.is_some() && !matches.is_present("global") && top.is_none() {
+        return Err("--memory-limit requires --global or --top".into());
+    }
+
+    // 没有--output的时候和uniq一样：IN_FILE [OUT_FILE]
+    let mut in_files = matches.values_of_lossy("files").unwrap();
+    let out_file = match matches.value_of("output") {
+        Some(out_name) => Some(out_name.to_string()),
+        None if in_files.len() > 2 => return Err(format!("extra operand '{}'", in_files[2]).into()),
+        None if in_files.len() == 2 => in_files.pop(),
+        None => None,
+    };
+
+    let format = match matches.value_of("format") {
+        Some("json") => Format::Json,
+        Some("tsv") => Format::Tsv,
+        _ => Format::Text,
+    };
+
+    // -D不带值就是none
+    let all_repeated = if matches.is_present("all_repeated") {
+        match matches.value_of("all_repeated") {
+            Some("prepend") => Some(Delimit::Prepend),
+            Some("separate") => Some(Delimit::Separate),
+            _ => Some(Delimit::None),
+        }
+    } else {
+        None
+    };
+
     Ok(Config {
-        //in_file: matches.value_of_lossy("in_file").unwrap().to_string(),
-        //in_file: matches.value_of_lossy("in_file").map(String::from).unwrap(),
-        //in_file: matches.value_of_lossy("in_file").map(|v| v.into()).unwrap(),
-        in_file: matches.value_of_lossy("in_file").map(Into::into).unwrap(),
-        out_file: matches.value_of("out_file").map(|v| v.to_string()),
+        in_files,
+        out_file,
         count: matches.is_present("count"),
+        repeated: matches.is_present("repeated"),
+        unique: matches.is_present("unique"),
+        all_repeated,
+        key: KeySpec {
+            delimiter: if matches.is_present("zero_terminated") {
+                b'\0'
+            } else {
+                b'\n'
+            },
+            skip_fields,
+            skip_chars,
+            check_chars,
+            ignore_case: matches.is_present("ignore_case"),
+        },
+        global: matches.is_present("global"),
+        top,
+        memory_limit,
+        format,
     })
 }
 
+// --------------------------------------------------
+// A byte count with an optional K, M or G (powers of 1024) suffix
+fn parse_size(val: &str) -> MyResult<usize> {
+    let split = val.find(|c: char| !c.is_ascii_digit()).unwrap_or(val.len());
+    let (num, suffix) = val.split_at(split);
+    let multiplier: usize = match suffix {
+        "" => 1,
+        "K" | "k" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("{}: invalid memory limit", val).into()),
+    };
+    num.parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("{}: invalid memory limit", val).into())
+}
+
+// --------------------------------------------------
+// 0也可以，和GNU一样报错信息里带上是哪个参数
+fn parse_count(val: Option<&str>, what: &str) -> MyResult<usize> {
+    match val {
+        None => Ok(0),
+        Some(val) => val
+            .parse()
+            .map_err(|_| format!("{}: invalid number of {}", val, what).into()),
+    }
+}
+
 pub fn run(config: Config) -> MyResult<()> {
     // println!("{:?}", config);
-    let mut file = open(&config.in_file)
-        .map_err(|e| format!("{}: {}", config.in_file, e))?;
+    // 输入要在创建输出文件之前全部打开，才能知道输出是不是其中之一
+    let mut inputs: Vec<Box<dyn Read>> = vec![];
+    let mut identities = vec![];
+    for filename in &config.in_files {
+        let (input, identity) = open(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        inputs.push(input);
+        identities.extend(identity);
+    }
+    let file = BufReader::new(
+        inputs
+            .into_iter()
+            .reduce(|chain, next| Box::new(chain.chain(next)))
+            .unwrap(),
+    );
 
-    let mut out_file: Box<dyn Write> = match &config.out_file {
-        Some(out_name) => Box::new(File::create(out_name)?),
-        _ => Box::new(io::stdout()),
+    let mut output = match &config.out_file {
+        Some(out_name) if out_name != "-" => {
+            Output::create(out_name, &identities).map_err(|e| format!("{}: {}", out_name, e))?
+        }
+        _ => Output::stdout(),
     };
 
-    let mut print = |count: u64, text: &str| -> MyResult<()>{
-        if count > 0 {
-            if config.count {
-                write!(out_file, "{:>4} {}", count, text)?;
-            } else {
-                write!(out_file, "{}", text)?;
-            }
+    if config.global || config.top.is_some() {
+        global(&config, file, Printer::new(&config, &mut output.wtr, true))?;
+    } else {
+        uniq(&config, file, Printer::new(&config, &mut output.wtr, false))?;
+    }
+    output.finish()
+}
+
+// --------------------------------------------------
+// Collapse adjacent lines with the same key
+fn uniq(config: &Config, mut file: impl BufRead, mut printer: Printer<impl Write>) -> MyResult<()> {
+    // 一组相同的行读完以后调用，-D的时候行已经边读边输出了
+    let delimiter = config.key.delimiter;
+    let print = |printer: &mut Printer<_>, group: &Entry| -> MyResult<()> {
+        if group.count > 0 && config.wanted(group.count) && config.all_repeated.is_none() {
+            printer.entry(group)?;
         };
         Ok(())
     };
 
-    let mut line = String::new();
-    let mut previous = String::new();
-    let mut count: u64 = 0;
+    let mut line = vec![];
+    let mut group = Entry::default();
+    let mut previous_key = vec![];
+    let mut line_num: u64 = 0;
+    let mut num_groups: u64 = 0;
     loop {
-        let bytes = file.read_line(&mut line)?;
+        let bytes = file.read_until(delimiter, &mut line)?;
         if bytes == 0 {
             break;
         }
+        line_num += 1;
 
-        if line.trim_end() != previous.trim_end() {
-            print(count, &previous)?;
-            previous = line.clone();
-            count = 0;
+        let key = config.key.key(&line);
+        if group.count == 0 || key != previous_key {
+            print(&mut printer, &group)?;
+            previous_key = key.into_owned();
+            group = Entry {
+                first: line_num,
+                line: line.clone(),
+                ..Default::default()
+            };
+        }
+        group.count += 1;
+        group.last = line_num;
+
+        // -D：第二行出现的时候才知道这组是重复的，把第一行补上
+        if let Some(delimit) = config.all_repeated.filter(|_| !config.unique) {
+            if group.count == 2 {
+                if delimit == Delimit::Prepend || delimit == Delimit::Separate && num_groups > 0 {
+                    printer.raw(&[delimiter])?;
+                }
+                printer.raw(&group.line)?;
+                num_groups += 1;
+            }
+            if group.count >= 2 {
+                printer.raw(&line)?;
+            }
         }
 
-        count += 1;
         line.clear();
     }
 
-    print(count, &previous)?;
+    print(&mut printer, &group)?;
 
-    Ok(())
+    printer.finish()
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
+// --------------------------------------------------
+// The reader and, when it can be found, which file it is
+fn open(filename: &str) -> io::Result<(Box<dyn Read>, Option<FileId>)> {
     match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+        "-" => Ok((Box::new(io::stdin()), stdin_id())),
+        _ => {
+            let file = File::open(filename)?;
+            let id = file_id(Path::new(filename), &file.metadata()?);
+            Ok((Box::new(file), Some(id)))
+        }
+    }
+}
+
+// --------------------------------------------------
+// Where the output goes. When OUT_FILE is also an input it can't be
+// truncated before the input is read, so the output goes to a temporary
+// file next to it that replaces it at the end with the same permissions.
+struct Output {
+    wtr: BufWriter<Box<dyn Write>>,
+    replace: Option<(TempPath, PathBuf)>,
+}
+
+impl Output {
+    fn stdout() -> Self {
+        Output {
+            wtr: BufWriter::new(Box::new(io::stdout())),
+            replace: None,
+        }
+    }
+
+    fn create(out_name: &str, inputs: &[FileId]) -> io::Result<Self> {
+        let path = Path::new(out_name);
+        let existing = fs::metadata(path)
+            .ok()
+            .filter(|meta| meta.is_file() && inputs.contains(&file_id(path, meta)));
+        let Some(meta) = existing else {
+            return Ok(Output {
+                wtr: BufWriter::new(Box::new(File::create(path)?)),
+                replace: None,
+            });
+        };
+
+        // 输出是符号链接的话替换它指向的文件，不替换链接本身
+        let target = fs::canonicalize(path)?;
+        let dir = target.parent().unwrap_or_else(|| Path::new("."));
+        let (file, temp_path) = NamedTempFile::new_in(dir)?.into_parts();
+        file.set_permissions(meta.permissions())?;
+        Ok(Output {
+            wtr: BufWriter::new(Box::new(file)),
+            replace: Some((temp_path, target)),
+        })
+    }
+
+    fn finish(self) -> MyResult<()> {
+        let Output { wtr, replace } = self;
+        let file = wtr.into_inner().map_err(|e| e.into_error())?;
+        drop(file);
+        if let Some((temp_path, target)) = replace {
+            temp_path
+                .persist(&target)
+                .map_err(|e| format!("{}: {}", target.display(), e.error))?;
+        }
+        Ok(())
+    }
+}
+
+// --------------------------------------------------
+// Identifies a file no matter which name it was opened by: device and
+// inode on Unix, the canonical path elsewhere
+#[cfg(unix)]
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(_path: &Path, meta: &fs::Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+
+// 标准输入也可能是重定向进来的输出文件
+#[cfg(unix)]
+fn stdin_id() -> Option<FileId> {
+    use std::os::fd::AsFd;
+    let fd = io::stdin().as_fd().try_clone_to_owned().ok()?;
+    let meta = File::from(fd).metadata().ok()?;
+    meta.is_file().then(|| file_id(Path::new("-"), &meta))
+}
+
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+#[cfg(not(unix))]
+fn file_id(path: &Path, _meta: &fs::Metadata) -> FileId {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(not(unix))]
+fn stdin_id() -> Option<FileId> {
+    None
+}
+
+// --------------------------------------------------
+// --global and --top: count every key across the whole input, then print
+// either the first line seen for each key, in first-seen order, or the N
+// most frequent ones.
+fn global(
+    config: &Config,
+    mut file: impl BufRead,
+    mut printer: Printer<impl Write>,
+) -> MyResult<()> {
+    let mut tally = Tally::new(config);
+    let delimiter = config.key.delimiter;
+    let mut line = vec![];
+    loop {
+        line.clear();
+        if file.read_until(delimiter, &mut line)? == 0 {
+            break;
+        }
+        tally.add(&line)?;
+    }
+
+    let mut entries = tally.finish()?;
+    match config.top {
+        Some(n) => {
+            // 最小堆里留着目前最多的n个，次数一样的先出现的排前面
+            let mut heap = BinaryHeap::with_capacity(n.min(1024) + 1);
+            while let Some(entry) = entries.next_entry()? {
+                heap.push(Reverse(TopEntry(entry)));
+                if heap.len() > n {
+                    heap.pop();
+                }
+            }
+            for Reverse(TopEntry(entry)) in heap.into_sorted_vec() {
+                printer.entry(&entry)?;
+            }
+        }
+        None => {
+            while let Some(entry) = entries.next_entry()? {
+                if config.wanted(entry.count) {
+                    printer.entry(&entry)?;
+                }
+            }
+        }
+    }
+    printer.finish()
+}
+
+// --------------------------------------------------
+// Writes the groups. Text is what uniq prints; JSON and TSV give every
+// group as a record with its count and first and last line numbers.
+struct Printer<'a, W: Write> {
+    config: &'a Config,
+    // --global和--top会打乱顺序，没有结束符的最后一行要补上
+    terminate: bool,
+    num_records: usize,
+    sink: Sink<W>,
+}
+
+enum Sink<W: Write> {
+    Plain(W),
+    Tsv(W),
+}
+
+const TSV_HEADER: &[u8] = b"count\tfirst_line\tlast_line\ttext\n";
+
+// TSV的字段里不能有制表符和换行，写成\t、\n、\r，反斜杠自己写成\\
+fn write_tsv_field(out: &mut impl Write, field: &[u8]) -> io::Result<()> {
+    let mut start = 0;
+    for (i, &b) in field.iter().enumerate() {
+        let escaped: &[u8] = match b {
+            b'\t' => b"\\t",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\\' => b"\\\\",
+            _ => continue,
+        };
+        out.write_all(&field[start..i])?;
+        out.write_all(escaped)?;
+        start = i + 1;
+    }
+    out.write_all(&field[start..])
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    fn new(config: &'a Config, out: W, terminate: bool) -> Self {
+        let sink = match config.format {
+            Format::Tsv => Sink::Tsv(out),
+            _ => Sink::Plain(out),
+        };
+        Printer {
+            config,
+            terminate,
+            num_records: 0,
+            sink,
+        }
+    }
+
+    fn entry(&mut self, entry: &Entry) -> MyResult<()> {
+        let delimiter = self.config.key.delimiter;
+        let text = entry.line.strip_suffix(&[delimiter]).unwrap_or(&entry.line);
+        match &mut self.sink {
+            Sink::Tsv(out) => {
+                // 第一行前面先写表头
+                if self.num_records == 0 {
+                    out.write_all(TSV_HEADER)?;
+                }
+                write!(out, "{}\t{}\t{}\t", entry.count, entry.first, entry.last)?;
+                write_tsv_field(out, text)?;
+                out.write_all(b"\n")?;
+            }
+            Sink::Plain(out) if self.config.format == Format::Json => {
+                let mut group = json!({
+                    "count": entry.count,
+                    "first_line": entry.first,
+                    "last_line": entry.last,
+                });
+                // JSON的字符串只能是UTF-8，别的行原样给出字节，不做替换
+                match str::from_utf8(text) {
+                    Ok(text) => group["text"] = json!(text),
+                    Err(_) => group["bytes"] = json!(text),
+                }
+                let separator = if self.num_records == 0 {
+                    "{\"groups\":[\n"
+                } else {
+                    ",\n"
+                };
+                out.write_all(separator.as_bytes())?;
+                serde_json::to_writer(&mut *out, &group)?;
+            }
+            Sink::Plain(out) => {
+                if self.config.count || self.config.top.is_some() {
+                    write!(out, "{:>4} ", entry.count)?;
+                }
+                out.write_all(&entry.line)?;
+                if self.terminate && entry.line.last() != Some(&delimiter) {
+                    out.write_all(&[delimiter])?;
+                }
+            }
+        }
+        self.num_records += 1;
+        Ok(())
+    }
+
+    // -D边读边输出，不分组；--format和-D不能一起用
+    fn raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Plain(out) => out.write_all(bytes),
+            Sink::Tsv(_) => unreachable!("--format conflicts with --all-repeated"),
+        }
+    }
+
+    fn finish(self) -> MyResult<()> {
+        match self.sink {
+            Sink::Tsv(mut out) => {
+                if self.num_records == 0 {
+                    out.write_all(TSV_HEADER)?;
+                }
+            }
+            Sink::Plain(mut out) => {
+                if self.config.format == Format::Json {
+                    writeln!(
+                        out,
+                        "{}]}}",
+                        if self.num_records == 0 {
+                            "{\"groups\":["
+                        } else {
+                            "\n"
+                        }
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// --------------------------------------------------
+// One group of equal lines: the first line, the line numbers of the first
+// and the last, and how many lines there were. Ordered by first line.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct Entry {
+    first: u64,
+    last: u64,
+    count: u64,
+    line: Vec<u8>,
+}
+
+// Order for --top: higher count is greater, and on equal counts the line
+// seen first is greater
+#[derive(PartialEq, Eq)]
+struct TopEntry(Entry);
+
+impl Ord for TopEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .count
+            .cmp(&other.0.count)
+            .then_with(|| other.0.first.cmp(&self.0.first))
+    }
+}
+
+impl PartialOrd for TopEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// --------------------------------------------------
+// Counts keys in a hash map. Once the map's rough size passes the memory
+// limit, it and every line after it are written to PARTITIONS temporary
+// files by hash of the key, so all lines with the same key land in the same
+// file. At the end each partition is counted on its own, which needs about
+// 1/PARTITIONS of the memory, and the results are merged back into
+// first-seen order.
+struct Tally<'a> {
+    config: &'a Config,
+    map: HashMap<Vec<u8>, Entry>,
+    mem: usize,
+    next: u64,
+    hasher: RandomState,
+    spill: Vec<BufWriter<File>>,
+}
+
+const PARTITIONS: usize = 64;
+// HashMap里每一项除了字符串本身大概还要这么多
+const ENTRY_OVERHEAD: usize = 64;
+
+impl<'a> Tally<'a> {
+    fn new(config: &'a Config) -> Self {
+        Tally {
+            config,
+            map: HashMap::new(),
+            mem: 0,
+            next: 1,
+            hasher: RandomState::new(),
+            spill: vec![],
+        }
+    }
+
+    fn add(&mut self, line: &[u8]) -> io::Result<()> {
+        let line_num = self.next;
+        self.next += 1;
+        let key = self.config.key.key(line);
+
+        if !self.spill.is_empty() {
+            let part = self.partition(&key);
+            return write_record(&mut self.spill[part], line_num, line_num, 1, line);
+        }
+
+        if let Some(entry) = self.map.get_mut(key.as_ref()) {
+            entry.count += 1;
+            entry.last = line_num;
+            return Ok(());
+        }
+        self.mem += key.len() + line.len() + ENTRY_OVERHEAD;
+        let line = line.to_vec();
+        self.map.insert(
+            key.into_owned(),
+            Entry {
+                first: line_num,
+                last: line_num,
+                count: 1,
+                line,
+            },
+        );
+
+        if self
+            .config
+            .memory_limit
+            .is_some_and(|limit| self.mem > limit)
+        {
+            self.start_spill()?;
+        }
+        Ok(())
+    }
+
+    fn partition(&self, key: &[u8]) -> usize {
+        (self.hasher.hash_one(key) % PARTITIONS as u64) as usize
+    }
+
+    fn start_spill(&mut self) -> io::Result<()> {
+        for _ in 0..PARTITIONS {
+            self.spill.push(BufWriter::new(tempfile::tempfile()?));
+        }
+        for (key, entry) in std::mem::take(&mut self.map) {
+            let part = self.partition(&key);
+            write_record(
+                &mut self.spill[part],
+                entry.first,
+                entry.last,
+                entry.count,
+                &entry.line,
+            )?;
+        }
+        self.mem = 0;
+        Ok(())
+    }
+
+    // Entries in first-seen order
+    fn finish(self) -> io::Result<Entries> {
+        if self.spill.is_empty() {
+            let mut entries: Vec<Entry> = self.map.into_values().collect();
+            entries.sort_unstable_by_key(|entry| Reverse(entry.first));
+            return Ok(Entries::Memory(entries));
+        }
+
+        // 每个分区单独合并计数，排好序写回一个临时文件，最后按first多路归并
+        let mut runs = vec![];
+        for part in self.spill {
+            let mut file = part.into_inner().map_err(|e| e.into_error())?;
+            file.seek(SeekFrom::Start(0))?;
+            let mut reader = BufReader::new(file);
+            let mut map: HashMap<Vec<u8>, Entry> = HashMap::new();
+            while let Some(record) = read_record(&mut reader)? {
+                let key = self.config.key.key(&record.line).into_owned();
+                match map.get_mut(&key) {
+                    Some(entry) => {
+                        entry.count += record.count;
+                        entry.last = entry.last.max(record.last);
+                        if record.first < entry.first {
+                            entry.first = record.first;
+                            entry.line = record.line;
+                        }
+                    }
+                    None => {
+                        map.insert(key, record);
+                    }
+                }
+            }
+
+            let mut entries: Vec<Entry> = map.into_values().collect();
+            entries.sort_unstable_by_key(|entry| entry.first);
+            let mut run = BufWriter::new(tempfile::tempfile()?);
+            for entry in &entries {
+                write_record(&mut run, entry.first, entry.last, entry.count, &entry.line)?;
+            }
+            let mut file = run.into_inner().map_err(|e| e.into_error())?;
+            file.seek(SeekFrom::Start(0))?;
+            runs.push(BufReader::new(file));
+        }
+
+        let mut heap = BinaryHeap::new();
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(entry) = read_record(run)? {
+                heap.push(Reverse((entry, i)));
+            }
+        }
+        Ok(Entries::Merge { runs, heap })
+    }
+}
+
+// --------------------------------------------------
+// Tally results, either still in memory or as sorted runs on disk
+enum Entries {
+    // 倒序放着，从后面pop
+    Memory(Vec<Entry>),
+    Merge {
+        runs: Vec<BufReader<File>>,
+        // 每个run的下一项和它是哪个run
+        heap: BinaryHeap<Reverse<(Entry, usize)>>,
+    },
+}
+
+impl Entries {
+    fn next_entry(&mut self) -> io::Result<Option<Entry>> {
+        match self {
+            Entries::Memory(entries) => Ok(entries.pop()),
+            Entries::Merge { runs, heap } => {
+                let Some(Reverse((entry, i))) = heap.pop() else {
+                    return Ok(None);
+                };
+                if let Some(next) = read_record(&mut runs[i])? {
+                    heap.push(Reverse((next, i)));
+                }
+                Ok(Some(entry))
+            }
+        }
+    }
+}
+
+// --------------------------------------------------
+// Spill records: first and last line numbers, count, length and the line
+// itself
+fn write_record(
+    out: &mut impl Write,
+    first: u64,
+    last: u64,
+    count: u64,
+    line: &[u8],
+) -> io::Result<()> {
+    out.write_all(&first.to_le_bytes())?;
+    out.write_all(&last.to_le_bytes())?;
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&(line.len() as u64).to_le_bytes())?;
+    out.write_all(line)
+}
+
+fn read_record(input: &mut impl Read) -> io::Result<Option<Entry>> {
+    let mut header = [0; 32];
+    match input.read_exact(&mut header) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        res => res?,
     }
+    let field = |i: usize| u64::from_le_bytes(header[i * 8..i * 8 + 8].try_into().unwrap());
+    let mut line = vec![0; field(3) as usize];
+    input.read_exact(&mut line)?;
+    Ok(Some(Entry {
+        first: field(0),
+        last: field(1),
+        count: field(2),
+        line,
+    }))
 }
@@ -124,7 +124,7 @@ fn run(test: &Test) -> TestResult {
 fn run_count(test: &Test) -> TestResult {
     let expected = fs::read_to_string(test.out_count)?;
     Command::cargo_bin(PRG)?
-        .args(&[test.input, "-c"])
+        .args([test.input, "-c"])
         .assert()
         .success()
         .stdout(expected);
@@ -162,12 +162,12 @@ fn run_outfile(test: &Test) -> TestResult {
     let outfile = NamedTempFile::new()?;
     let outpath = &outfile.path().to_str().unwrap();
     Command::cargo_bin(PRG)?
-        .args(&[test.input, outpath])
+        .args([test.input, outpath])
         .assert()
         .success()
         .stdout("");
 
-    let contents = fs::read_to_string(&outpath)?;
+    let contents = fs::read_to_string(outpath)?;
     assert_eq!(&expected, &contents);
 
     Ok(())
@@ -179,13 +179,13 @@ fn run_outfile_count(test: &Test) -> TestResult {
     let outpath = &outfile.path().to_str().unwrap();
 
     Command::cargo_bin(PRG)?
-        .args(&[test.input, outpath, "--count"])
+        .args([test.input, outpath, "--count"])
         .assert()
         .success()
         .stdout("");
 
     let expected = fs::read_to_string(test.out_count)?;
-    let contents = fs::read_to_string(&outpath)?;
+    let contents = fs::read_to_string(outpath)?;
     assert_eq!(&expected, &contents);
 
     Ok(())
@@ -198,13 +198,13 @@ fn run_stdin_outfile_count(test: &Test) -> TestResult {
     let outpath = &outfile.path().to_str().unwrap();
 
     Command::cargo_bin(PRG)?
-        .args(&["-", outpath, "-c"])
+        .args(["-", outpath, "-c"])
         .write_stdin(input)
         .assert()
         .stdout("");
 
     let expected = fs::read_to_string(test.out_count)?;
-    let contents = fs::read_to_string(&outpath)?;
+    let contents = fs::read_to_string(outpath)?;
     assert_eq!(&expected, &contents);
 
     Ok(())
@@ -605,3 +605,500 @@ fn t6_outfile_count() -> TestResult {
 fn t6_stdin_outfile_count() -> TestResult {
     run_stdin_outfile_count(&T6)
 }
+
+// --------------------------------------------------
+fn run_args(args: &[&str], expected_file: &str) -> TestResult {
+    let expected = fs::read_to_string(expected_file)?;
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+const FIELDS: &str = "tests/inputs/fields.txt";
+
+// --------------------------------------------------
+#[test]
+fn three_repeated() -> TestResult {
+    run_args(&["-d", THREE.input], "tests/expected/three.txt.d.out")
+}
+
+#[test]
+fn three_unique() -> TestResult {
+    run_args(&["--unique", THREE.input], "tests/expected/three.txt.u.out")
+}
+
+#[test]
+fn three_repeated_and_unique() -> TestResult {
+    run_args(&["-d", "-u", THREE.input], "tests/inputs/empty.txt")
+}
+
+#[test]
+fn three_all_repeated() -> TestResult {
+    run_args(&["-D", THREE.input], "tests/expected/three.txt.D.out")
+}
+
+#[test]
+fn three_all_repeated_separate() -> TestResult {
+    run_args(
+        &["--all-repeated=separate", THREE.input],
+        "tests/expected/three.txt.D-separate.out",
+    )
+}
+
+#[test]
+fn three_all_repeated_prepend() -> TestResult {
+    run_args(
+        &["-D=prepend", THREE.input],
+        "tests/expected/three.txt.D-prepend.out",
+    )
+}
+
+#[test]
+fn dies_all_repeated_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-D", "-c", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn dies_bad_all_repeated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--all-repeated=middle", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("isn't a valid value"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fields_skip_fields() -> TestResult {
+    run_args(&["-f", "1", FIELDS], "tests/expected/fields.txt.f1.out")
+}
+
+#[test]
+fn fields_skip_fields_ignore_case() -> TestResult {
+    run_args(&["-f1", "-i", FIELDS], "tests/expected/fields.txt.f1.i.out")
+}
+
+#[test]
+fn fields_skip_fields_ignore_case_count() -> TestResult {
+    run_args(
+        &["--skip-fields=1", "--ignore-case", "-c", FIELDS],
+        "tests/expected/fields.txt.f1.i.c.out",
+    )
+}
+
+#[test]
+fn fields_skip_chars_check_chars() -> TestResult {
+    run_args(
+        &["-s", "2", "-w", "7", "-i", FIELDS],
+        "tests/expected/fields.txt.s2.w7.i.out",
+    )
+}
+
+#[test]
+fn fields_all_options() -> TestResult {
+    run_args(
+        &["-f1", "-s1", "-w5", "-i", "--all-repeated=separate", FIELDS],
+        "tests/expected/fields.txt.f1.s1.w5.i.D-separate.out",
+    )
+}
+
+#[test]
+fn dies_bad_skip_fields() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "x", FIELDS])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "x: invalid number of fields to skip",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_global() -> TestResult {
+    run_args(
+        &["--global", THREE.input],
+        "tests/expected/three.txt.global.out",
+    )
+}
+
+#[test]
+fn three_global_count() -> TestResult {
+    run_args(
+        &["-g", "-c", THREE.input],
+        "tests/expected/three.txt.global.c.out",
+    )
+}
+
+#[test]
+fn three_global_unique() -> TestResult {
+    run_args(&["--global", "-u", THREE.input], "tests/inputs/empty.txt")
+}
+
+#[test]
+fn three_top() -> TestResult {
+    run_args(
+        &["--top", "2", THREE.input],
+        "tests/expected/three.txt.top2.out",
+    )
+}
+
+#[test]
+fn fields_global_ignore_case() -> TestResult {
+    run_args(
+        &["-f1", "-i", "--global", FIELDS],
+        "tests/expected/fields.txt.f1.i.global.out",
+    )
+}
+
+#[test]
+fn dies_top_all_repeated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--top", "2", "-D", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn dies_bad_memory_limit() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--global", "--memory-limit", "10X", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("10X: invalid memory limit"));
+    Ok(())
+}
+
+#[test]
+fn dies_memory_limit_without_mode() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--memory-limit", "1M", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--global"));
+    Ok(())
+}
+
+// --------------------------------------------------
+// 内存限制很小时要写临时文件，结果必须和不限制时一样
+#[test]
+fn global_spill_matches_memory() -> TestResult {
+    let mut rng = rand::thread_rng();
+    let mut input = String::new();
+    for _ in 0..20_000 {
+        input.push_str(&format!("line {}\n", rng.gen_range(0..3_000)));
+    }
+
+    for args in [
+        vec!["--global", "-c"],
+        vec!["--top", "50"],
+        vec!["--global", "-d"],
+        vec!["--global", "--format", "json"],
+    ] {
+        let expected = Command::cargo_bin(PRG)?
+            .args(&args)
+            .write_stdin(input.clone())
+            .output()?;
+        assert!(expected.status.success());
+
+        let spilled = Command::cargo_bin(PRG)?
+            .args(&args)
+            .args(["--memory-limit", "1K"])
+            .write_stdin(input.clone())
+            .output()?;
+        assert!(spilled.status.success());
+        assert_eq!(
+            String::from_utf8(spilled.stdout)?,
+            String::from_utf8(expected.stdout)?
+        );
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+// 输出文件就是输入文件时不能先把它清空
+#[test]
+fn same_file_in_place() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("three.txt");
+    fs::copy(THREE.input, &path)?;
+    let path = path.to_str().unwrap();
+
+    Command::cargo_bin(PRG)?
+        .args([path, path])
+        .assert()
+        .success()
+        .stdout("");
+
+    let expected = fs::read_to_string(THREE.out)?;
+    assert_eq!(expected, fs::read_to_string(path)?);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn same_file_through_symlink_keeps_permissions() -> TestResult {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("three.txt");
+    fs::copy(THREE.input, &path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640))?;
+    let link = dir.path().join("link.txt");
+    symlink(&path, &link)?;
+
+    Command::cargo_bin(PRG)?
+        .args(["-c", path.to_str().unwrap(), link.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let expected = fs::read_to_string(THREE.out_count)?;
+    assert_eq!(expected, fs::read_to_string(&path)?);
+    assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+    assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o640);
+    Ok(())
+}
+
+#[test]
+fn same_file_as_stdin() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("three.txt");
+    fs::copy(THREE.input, &path)?;
+
+    Command::cargo_bin(PRG)?
+        .args(["-", path.to_str().unwrap()])
+        .pipe_stdin(&path)?
+        .assert()
+        .success();
+
+    let expected = fs::read_to_string(THREE.out)?;
+    assert_eq!(expected, fs::read_to_string(&path)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn several_inputs_to_stdout() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "-o", "-", TWO.input, TWO.input])
+        .assert()
+        .success()
+        .stdout("   4 a\n");
+    Ok(())
+}
+
+#[test]
+fn several_inputs_to_output() -> TestResult {
+    let outfile = NamedTempFile::new()?;
+    let outpath = outfile.path().to_str().unwrap();
+    Command::cargo_bin(PRG)?
+        .args(["--output", outpath, TWO.input, TWO.input])
+        .assert()
+        .success()
+        .stdout("");
+
+    let expected = fs::read_to_string(TWO.out)?;
+    assert_eq!(expected, fs::read_to_string(outpath)?);
+    Ok(())
+}
+
+#[test]
+fn dies_extra_operand() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([ONE.input, TWO.input, THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "extra operand '{}'",
+            THREE.input
+        )));
+    Ok(())
+}
+
+// --------------------------------------------------
+// 不是UTF-8的输入，按字节比较
+fn run_bytes(args: &[&str], expected_file: &str) -> TestResult {
+    let expected = fs::read(expected_file)?;
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+const BINARY: &str = "tests/inputs/binary.txt";
+const NUL: &str = "tests/inputs/nul.txt";
+
+#[test]
+fn binary() -> TestResult {
+    run_bytes(&[BINARY], "tests/expected/binary.txt.out")
+}
+
+#[test]
+fn binary_count() -> TestResult {
+    run_bytes(&["-c", BINARY], "tests/expected/binary.txt.c.out")
+}
+
+#[test]
+fn binary_ignore_case() -> TestResult {
+    run_bytes(&["-i", BINARY], "tests/expected/binary.txt.i.out")
+}
+
+#[test]
+fn binary_skip_and_check() -> TestResult {
+    run_bytes(
+        &["-f1", "-s1", "-w2", BINARY],
+        "tests/expected/binary.txt.s1.w2.out",
+    )
+}
+
+#[test]
+fn trailing_blanks_are_compared() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin("a  \na\na\n")
+        .assert()
+        .success()
+        .stdout("a  \na\n");
+    Ok(())
+}
+
+#[test]
+fn zero_terminated() -> TestResult {
+    run_bytes(&["-z", NUL], "tests/expected/nul.txt.z.out")
+}
+
+#[test]
+fn zero_terminated_count_ignore_case() -> TestResult {
+    run_bytes(
+        &["--zero-terminated", "-c", "-i", NUL],
+        "tests/expected/nul.txt.z.c.i.out",
+    )
+}
+
+#[test]
+fn zero_terminated_all_repeated() -> TestResult {
+    run_bytes(
+        &["-z", "-i", "--all-repeated=separate", NUL],
+        "tests/expected/nul.txt.z.D-separate.i.out",
+    )
+}
+
+#[test]
+fn zero_terminated_global() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-z", "--global"])
+        .write_stdin(&b"b\0a\xff\0b\0a\xff"[..])
+        .assert()
+        .success()
+        .stdout(&b"b\0a\xff\0"[..]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_json() -> TestResult {
+    run_args(
+        &["--format", "json", THREE.input],
+        "tests/expected/three.txt.json",
+    )
+}
+
+#[test]
+fn three_tsv() -> TestResult {
+    run_args(
+        &["--format=tsv", THREE.input],
+        "tests/expected/three.txt.tsv",
+    )
+}
+
+#[test]
+fn empty_json() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", EMPTY.input])
+        .assert()
+        .success()
+        .stdout("{\"groups\":[]}\n");
+    Ok(())
+}
+
+#[test]
+fn fields_global_json() -> TestResult {
+    run_args(
+        &["-g", "-i", "-f1", "--format", "json", FIELDS],
+        "tests/expected/fields.txt.f1.i.global.json",
+    )
+}
+
+#[test]
+fn binary_json() -> TestResult {
+    run_args(
+        &["--format", "json", BINARY],
+        "tests/expected/binary.txt.json",
+    )
+}
+
+// 只有非法字节不一样的两行，JSON里也要分得出来
+#[test]
+fn invalid_utf8_json_is_lossless() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json"])
+        .write_stdin(&b"a\xff\na\xfe\nok\n"[..])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "{\"groups\":[\n",
+            "{\"count\":1,\"first_line\":1,\"last_line\":1,\"bytes\":[97,255]},\n",
+            "{\"count\":1,\"first_line\":2,\"last_line\":2,\"bytes\":[97,254]},\n",
+            "{\"count\":1,\"first_line\":3,\"last_line\":3,\"text\":\"ok\"}\n",
+            "]}\n"
+        ));
+    Ok(())
+}
+
+#[test]
+fn zero_terminated_repeated_tsv() -> TestResult {
+    run_args(
+        &["-z", "-d", "--format", "tsv", NUL],
+        "tests/expected/nul.txt.z.d.tsv",
+    )
+}
+
+// 字段里的制表符、换行和反斜杠转义成\t、\n、\\，不用CSV的引号
+#[test]
+fn tsv_escapes_fields() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-z", "--format", "tsv"])
+        .write_stdin(&b"a\tb\0\"q\"\0c\\d\r\n\0"[..])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "count\tfirst_line\tlast_line\ttext\n",
+            "1\t1\t1\ta\\tb\n",
+            "1\t2\t2\t\"q\"\n",
+            "1\t3\t3\tc\\\\d\\r\\n\n",
+        ));
+    Ok(())
+}
+
+#[test]
+fn dies_format_all_repeated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "json", "-D", THREE.input])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
@@ -1,7 +1,17 @@
 use crate::EntryType::*;
 use clap::{App, Arg};
-use regex::Regex;
-use std::error::Error;
+use regex::bytes::{Regex, RegexBuilder};
+use std::{
+    error::Error,
+    ffi::{OsStr, OsString},
+    fmt::Debug,
+    fs,
+    io::{self, Write},
+    iter::Peekable,
+    path::PathBuf,
+    slice,
+    time::{Duration, SystemTime},
+};
 use walkdir::{WalkDir, DirEntry};
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
@@ -15,16 +25,36 @@ enum EntryType {
 
 #[derive(Debug)]
 pub struct Config {
-    paths: Vec<String>,
-    names: Vec<Regex>,
-    entry_types: Vec<EntryType>,
+    paths: Vec<PathBuf>,
+    // --name、--type和路径后面的表达式合在一起
+    expr: Expr,
+    depth: Depth,
 }
 
 pub fn get_args() -> MyResult<Config> {
+    // 路径后面第一个像-name、!、(这样的参数开始就是表达式，clap不认识这些。
+    // 参数不一定是UTF-8，只在找表达式开头的时候转一下
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let start = args
+        .iter()
+        .skip(1)
+        .position(|arg| is_expression_start(&arg.to_string_lossy()))
+        .map_or(args.len(), |i| i + 1);
+
     let matches = App::new("findr")
         .version("0.1.0")
         .author("Ken Youens-Clark <kyclark@gmail.com>")
         .about("Rust find")
+        .usage("findr [FLAGS] [OPTIONS] [PATH]... [EXPRESSION]")
+        .after_help(
+            "EXPRESSION is made of tests joined by operators, as in find:\n    \
+             tests:     -name GLOB, -iname GLOB, -regex RE, -type f|d|l, -true, -false,\n               \
+             -size [+-]N[bcwkMG], -mtime|-atime|-ctime [+-]DAYS, -newer FILE,\n               \
+             -perm [-/]MODE, -user NAME, -group NAME, -empty\n    \
+             options:   -mindepth N, -maxdepth N\n    \
+             operators: ( EXPR ), ! EXPR, -not EXPR, EXPR -a EXPR, EXPR -and EXPR,\n               \
+             EXPR EXPR, EXPR -o EXPR, EXPR -or EXPR",
+        )
         .arg(
             Arg::with_name("paths")
                 .value_name("PATH")
@@ -51,21 +81,21 @@ pub fn get_args() -> MyResult<Config> {
                 .takes_value(true)
                 .multiple(true),
         )
-        .get_matches();
+        .get_matches_from(&args[..start]);
 
     let names = matches
-        .values_of_lossy("names")
+        .values_of_os("names")
         // 这个是Option::map返回的还是Option
         .map(|vals| {
-            vals.into_iter()
-                .map(|name| {
-                    // 这个返回一个Result
-                    Regex::new(&name)
-                        // 对于invalid regexes，使用Result::map_err来创建一个可读的错误信息
-                        .map_err(|_| format!("Invalid --name \"{}\"", name))
-                })
-                // 收集成Result<Vec>
-                .collect::<Result<Vec<_>, _>>()
+            vals.map(|name| {
+                // 这个返回一个Result，regex只能写成UTF-8
+                name.to_str()
+                    .and_then(|re| Regex::new(re).ok())
+                    // 对于invalid regexes，使用Option::ok_or_else来创建一个可读的错误信息
+                    .ok_or_else(|| format!("Invalid --name \"{}\"", name.to_string_lossy()))
+            })
+            // 收集成Result<Vec>
+            .collect::<Result<Vec<_>, _>>()
         })
         // 从Option::map返回，会多个Option
         // 将Option of a Result转换成Result of a Option
@@ -73,13 +103,13 @@ pub fn get_args() -> MyResult<Config> {
         // 解包失败用默认，默认是个empty vector
         .unwrap_or_default();
 
-    let entry_types = matches
-        .values_of_lossy("types")
+    let entry_types: Vec<EntryType> = matches
+        .values_of("types")
         // 使用Option::map handle Some(vals)
         .map(|vals| {
-            vals.iter()
+            vals
                 // Iterator::map to check each of the provided values
-                .map(|val| match val.as_str() {
+                .map(|val| match val {
                     "d" => Dir,
                     "f" => File,
                     "l" => Link,
@@ -91,41 +121,787 @@ pub fn get_args() -> MyResult<Config> {
         // default empty vector
         .unwrap_or_default();
 
-    Ok(Config{
-        paths: matches.values_of_lossy("paths").unwrap(),
-        names,
-        entry_types,
+    // --name和--type：任意一个名字并且任意一个类型
+    let (expr, depth) = parse_expression(&args[start..])?;
+    let any = |tests: Vec<Expr>| tests.into_iter().reduce(Expr::or).unwrap_or(Expr::True);
+    let expr = any(names.into_iter().map(|re| Expr::test(Name(re))).collect())
+        .and(any(entry_types
+            .into_iter()
+            .map(|t| Expr::test(Type(t)))
+            .collect()))
+        .and(expr);
+
+    Ok(Config {
+        paths: matches
+            .values_of_os("paths")
+            .unwrap()
+            .map(PathBuf::from)
+            .collect(),
+        expr,
+        depth,
     })
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    // Create a similar closure to filter entries by any of the types.
-    let type_filter = |entry: &DirEntry| {
-        config.entry_types.is_empty()
-            || config
-                .entry_types
-                .iter()
-                // 只要iter()里有一个true，整体是true
-                .any(|entry_type| match entry_type {
-                    Link => entry.file_type().is_symlink(),
-                    Dir => entry.file_type().is_dir(),
-                    File => entry.file_type().is_file(),
-                    })
+// --------------------------------------------------
+// A test in the expression, like -name or -type. A new test implements
+// this and gets a line in PRIMARIES; nothing else needs to change.
+trait Predicate: Debug {
+    fn matches(&self, entry: &DirEntry) -> bool;
+}
+
+// --------------------------------------------------
+// The expression after the paths. And and Or only look at the right side
+// when the left side hasn't decided the answer.
+#[derive(Debug)]
+enum Expr {
+    True,
+    Test(Box<dyn Predicate>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn test(predicate: impl Predicate + 'static) -> Self {
+        Expr::Test(Box::new(predicate))
+    }
+
+    // 和True合在一起就不用多算一次
+    fn and(self, other: Expr) -> Self {
+        match (self, other) {
+            (Expr::True, expr) | (expr, Expr::True) => expr,
+            (left, right) => Expr::And(Box::new(left), Box::new(right)),
+        }
+    }
+
+    fn or(self, other: Expr) -> Self {
+        Expr::Or(Box::new(self), Box::new(other))
+    }
+
+    fn matches(&self, entry: &DirEntry) -> bool {
+        match self {
+            Expr::True => true,
+            Expr::Test(predicate) => predicate.matches(entry),
+            Expr::Not(expr) => !expr.matches(entry),
+            Expr::And(left, right) => left.matches(entry) && right.matches(entry),
+            Expr::Or(left, right) => left.matches(entry) || right.matches(entry),
+        }
+    }
+}
+
+// --------------------------------------------------
+// --name: the file name matches
+#[derive(Debug)]
+struct Name(Regex);
+
+impl Predicate for Name {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        self.0.is_match(entry.file_name().as_encoded_bytes())
+    }
+}
+
+// -name and -iname: the file name matches the glob. Like fnmatch, a glob or
+// file name that isn't UTF-8 is matched a byte at a time.
+#[derive(Debug)]
+struct Glob {
+    chars: Option<Regex>,
+    bytes: Regex,
+}
+
+impl Predicate for Glob {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        let name = entry.file_name();
+        match (&self.chars, name.to_str()) {
+            (Some(re), Some(_)) => re.is_match(name.as_encoded_bytes()),
+            _ => self.bytes.is_match(name.as_encoded_bytes()),
+        }
+    }
+}
+
+// -regex: the whole path matches
+#[derive(Debug)]
+struct PathRegex(Regex);
+
+impl Predicate for PathRegex {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        self.0.is_match(entry.path().as_os_str().as_encoded_bytes())
+    }
+}
+
+// -type and --type
+#[derive(Debug)]
+struct Type(EntryType);
+
+impl Predicate for Type {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        match self.0 {
+            Link => entry.file_type().is_symlink(),
+            Dir => entry.file_type().is_dir(),
+            File => entry.file_type().is_file(),
+        }
+    }
+}
+
+// -true and -false
+#[derive(Debug)]
+struct Always(bool);
+
+impl Predicate for Always {
+    fn matches(&self, _entry: &DirEntry) -> bool {
+        self.0
+    }
+}
+
+// --------------------------------------------------
+// +N is more than N, -N is less than N and N is exactly N
+#[derive(Debug, Clone, Copy)]
+enum Cmp {
+    Less(u64),
+    Equal(u64),
+    Greater(u64),
+}
+
+impl Cmp {
+    fn test(&self, value: u64) -> bool {
+        match *self {
+            Cmp::Less(n) => value < n,
+            Cmp::Equal(n) => value == n,
+            Cmp::Greater(n) => value > n,
+        }
+    }
+}
+
+// -size: the size in UNITs, rounded up like find does, so -size -1M is
+// only empty files
+#[derive(Debug)]
+struct Size {
+    cmp: Cmp,
+    unit: u64,
+}
+
+impl Predicate for Size {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        entry
+            .metadata()
+            .is_ok_and(|meta| self.cmp.test(meta.len().div_ceil(self.unit)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TimeField {
+    Modified,
+    Accessed,
+    Changed,
+}
+
+fn entry_time(entry: &DirEntry, field: TimeField) -> Option<SystemTime> {
+    let meta = entry.metadata().ok()?;
+    match field {
+        TimeField::Modified => meta.modified().ok(),
+        TimeField::Accessed => meta.accessed().ok(),
+        // inode的修改时间只有Unix有
+        #[cfg(unix)]
+        TimeField::Changed => {
+            use std::os::unix::fs::MetadataExt;
+            let secs = Duration::from_secs(meta.ctime().max(0) as u64);
+            Some(SystemTime::UNIX_EPOCH + secs + Duration::from_nanos(meta.ctime_nsec() as u64))
+        }
+        #[cfg(not(unix))]
+        TimeField::Changed => meta.created().ok(),
+    }
+}
+
+// -mtime, -atime and -ctime: whole days since the time, rounded down
+#[derive(Debug)]
+struct Age {
+    field: TimeField,
+    cmp: Cmp,
+    now: SystemTime,
+}
+
+impl Predicate for Age {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        entry_time(entry, self.field).is_some_and(|time| {
+            // 将来的时间算0天
+            let age = self.now.duration_since(time).unwrap_or_default();
+            self.cmp.test(age.as_secs() / (24 * 60 * 60))
+        })
+    }
+}
+
+// -newer: modified after FILE was
+#[derive(Debug)]
+struct Newer(SystemTime);
+
+impl Predicate for Newer {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        entry_time(entry, TimeField::Modified).is_some_and(|time| time > self.0)
+    }
+}
+
+// -perm MODE is exactly MODE, -perm -MODE has all of its bits and
+// -perm /MODE has any of them
+#[derive(Debug)]
+enum Perm {
+    Exact(u32),
+    All(u32),
+    Any(u32),
+}
+
+impl Predicate for Perm {
+    #[cfg(unix)]
+    fn matches(&self, entry: &DirEntry) -> bool {
+        use std::os::unix::fs::PermissionsExt;
+        entry.metadata().is_ok_and(|meta| {
+            let mode = meta.permissions().mode() & 0o7777;
+            match *self {
+                Perm::Exact(bits) => mode == bits,
+                Perm::All(bits) => mode & bits == bits,
+                // 和find一样，/000什么都匹配
+                Perm::Any(bits) => bits == 0 || mode & bits != 0,
+            }
+        })
+    }
+
+    // 没有Unix权限位，parse_perm已经报错了
+    #[cfg(not(unix))]
+    fn matches(&self, _entry: &DirEntry) -> bool {
+        false
+    }
+}
+
+// -user and -group
+#[cfg(unix)]
+#[derive(Debug)]
+enum Owner {
+    User(u32),
+    Group(u32),
+}
+
+#[cfg(unix)]
+impl Predicate for Owner {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        entry.metadata().is_ok_and(|meta| match *self {
+            Owner::User(uid) => meta.uid() == uid,
+            Owner::Group(gid) => meta.gid() == gid,
+        })
+    }
+}
+
+// -empty: a regular file with no bytes or a directory with no entries
+#[derive(Debug)]
+struct Empty;
 
+impl Predicate for Empty {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        let file_type = entry.file_type();
+        if file_type.is_file() {
+            entry.metadata().is_ok_and(|meta| meta.len() == 0)
+        } else if file_type.is_dir() {
+            fs::read_dir(entry.path()).is_ok_and(|mut dir| dir.next().is_none())
+        } else {
+            false
+        }
+    }
+}
+
+// --------------------------------------------------
+// -mindepth and -maxdepth aren't tests but WalkDir options. Like in find
+// they apply to the whole search wherever they are in the expression.
+#[derive(Debug, Default)]
+struct Depth {
+    min: Option<usize>,
+    max: Option<usize>,
+}
+
+// --------------------------------------------------
+type Tokens<'a> = Peekable<slice::Iter<'a, OsString>>;
+
+struct Parser<'a> {
+    tokens: Tokens<'a>,
+    depth: Depth,
+}
+
+// Builds the test for a primary from its arguments
+type ParsePrimary = fn(&str, &mut Parser) -> MyResult<Box<dyn Predicate>>;
+
+const PRIMARIES: &[(&str, ParsePrimary)] = &[
+    ("-name", parse_name),
+    ("-iname", parse_name),
+    ("-regex", parse_regex),
+    ("-type", parse_type),
+    ("-true", |_, _| Ok(Box::new(Always(true)))),
+    ("-false", |_, _| Ok(Box::new(Always(false)))),
+    ("-size", parse_size),
+    ("-mtime", parse_age),
+    ("-atime", parse_age),
+    ("-ctime", parse_age),
+    ("-newer", parse_newer),
+    ("-perm", parse_perm),
+    ("-user", parse_owner),
+    ("-group", parse_owner),
+    ("-empty", |_, _| Ok(Box::new(Empty))),
+    ("-mindepth", parse_depth),
+    ("-maxdepth", parse_depth),
+];
+
+const OPERATORS: &[&str] = &["!", "(", ")", "-not", "-a", "-and", "-o", "-or"];
+
+fn is_expression_start(arg: &str) -> bool {
+    OPERATORS.contains(&arg) || PRIMARIES.iter().any(|(name, _)| *name == arg)
+}
+
+// 取primary后面的参数，文件名和glob可以不是UTF-8
+fn primary_os_arg<'a>(name: &str, parser: &mut Parser<'a>) -> MyResult<&'a OsStr> {
+    parser
+        .tokens
+        .next()
+        .map(OsString::as_os_str)
+        .ok_or_else(|| format!("missing argument to `{}'", name).into())
+}
+
+// 数字、模式这些参数不是UTF-8反正也不对，转一下好报错
+fn primary_arg(name: &str, parser: &mut Parser) -> MyResult<String> {
+    Ok(primary_os_arg(name, parser)?.to_string_lossy().into_owned())
+}
+
+fn parse_name(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let glob = primary_os_arg(name, parser)?;
+    let build = |re: String, unicode: bool| {
+        RegexBuilder::new(&re)
+            .case_insensitive(name == "-iname")
+            // *和?也要能匹配文件名里的换行
+            .dot_matches_new_line(true)
+            .unicode(unicode)
+            .build()
+            .map_err(|_| format!("Invalid {} \"{}\"", name, glob.to_string_lossy()))
+    };
+    let chars = glob
+        .to_str()
+        .map(|glob| build(glob_to_regex(glob), true))
+        .transpose()?;
+    // 按字节匹配的时候每个字节当一个字符
+    let latin1: String = glob
+        .as_encoded_bytes()
+        .iter()
+        .map(|&b| char::from(b))
+        .collect();
+    let bytes = build(glob_to_regex(&latin1), false)?;
+    Ok(Box::new(Glob { chars, bytes }))
+}
+
+fn parse_regex(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let pattern = primary_os_arg(name, parser)?;
+    // 和find一样，.也匹配换行
+    let re = pattern
+        .to_str()
+        .and_then(|pattern| {
+            RegexBuilder::new(&format!("^(?:{})$", pattern))
+                .dot_matches_new_line(true)
+                .build()
+                .ok()
+        })
+        .ok_or_else(|| format!("Invalid {} \"{}\"", name, pattern.to_string_lossy()))?;
+    Ok(Box::new(PathRegex(re)))
+}
+
+// -type f,d 是任意一个
+fn parse_type(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let types = primary_arg(name, parser)?;
+    let mut expr: Option<Expr> = None;
+    for val in types.split(',') {
+        let entry_type = match val {
+            "d" => Dir,
+            "f" => File,
+            "l" => Link,
+            _ => return Err(format!("Unknown argument to {}: {}", name, val).into()),
+        };
+        let test = Expr::test(Type(entry_type));
+        expr = Some(match expr {
+            Some(expr) => expr.or(test),
+            None => test,
+        });
+    }
+    Ok(Box::new(expr.unwrap()))
+}
+
+// [+-]N，后面可以跟单位
+fn parse_cmp<'a>(name: &str, val: &'a str) -> MyResult<(Cmp, &'a str)> {
+    let (cmp, rest): (fn(u64) -> Cmp, &str) = match val.as_bytes().first() {
+        Some(b'+') => (Cmp::Greater, &val[1..]),
+        Some(b'-') => (Cmp::Less, &val[1..]),
+        _ => (Cmp::Equal, val),
+    };
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    match rest[..digits].parse() {
+        Ok(n) => Ok((cmp(n), &rest[digits..])),
+        Err(_) => Err(format!("invalid argument `{}' to `{}'", val, name).into()),
+    }
+}
+
+fn parse_size(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let val = primary_arg(name, parser)?;
+    let (cmp, suffix) = parse_cmp(name, &val)?;
+    let unit = match suffix {
+        "" | "b" => 512,
+        "c" => 1,
+        "w" => 2,
+        "k" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("invalid -size type `{}'", suffix).into()),
     };
+    Ok(Box::new(Size { cmp, unit }))
+}
 
-    // Create a closure to filter entries on any of the regular expressions.
-    let name_filter = |entry: &DirEntry| {
-        config.names.is_empty()
-            || config
-                .names
-                .iter()
-                .any(|re| re.is_match(&entry.file_name().to_string_lossy()))
+fn parse_age(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let val = primary_arg(name, parser)?;
+    let cmp = match parse_cmp(name, &val)? {
+        (cmp, "") => cmp,
+        _ => return Err(format!("invalid argument `{}' to `{}'", val, name).into()),
+    };
+    let field = match name {
+        "-atime" => TimeField::Accessed,
+        "-ctime" => TimeField::Changed,
+        _ => TimeField::Modified,
     };
+    Ok(Box::new(Age {
+        field,
+        cmp,
+        now: SystemTime::now(),
+    }))
+}
+
+// 不跟随符号链接，和find -P一样
+fn parse_newer(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let file = primary_os_arg(name, parser)?;
+    let time = fs::symlink_metadata(file)
+        .and_then(|meta| meta.modified())
+        .map_err(|e| format!("{}: {}", file.to_string_lossy(), e))?;
+    Ok(Box::new(Newer(time)))
+}
 
+fn parse_perm(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let val = primary_arg(name, parser)?;
+    let (kind, mode): (fn(u32) -> Perm, &str) = match val.as_bytes().first() {
+        Some(b'-') => (Perm::All, &val[1..]),
+        Some(b'/') => (Perm::Any, &val[1..]),
+        _ => (Perm::Exact, &val),
+    };
+    match parse_mode(mode) {
+        Some(bits) if cfg!(unix) => Ok(Box::new(kind(bits))),
+        Some(_) => Err(format!("{} is not supported on this platform", name).into()),
+        None => Err(format!("invalid mode `{}'", val).into()),
+    }
+}
+
+// 八进制，或者像chmod那样的u+rw,go=r，从0开始算
+fn parse_mode(mode: &str) -> Option<u32> {
+    if !mode.is_empty() && mode.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|&bits| bits <= 0o7777);
+    }
+
+    let mut bits = 0;
+    for clause in mode.split(',') {
+        let mut who = 0;
+        let mut chars = clause.chars().peekable();
+        while let Some(c) = chars.next_if(|c| "ugoa".contains(*c)) {
+            who |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                _ => 0o7777,
+            };
+        }
+        if who == 0 {
+            who = 0o7777;
+        }
+
+        // 每个操作符后面跟着权限
+        chars.peek()?;
+        while let Some(op) = chars.next() {
+            let mut perms = 0;
+            while let Some(c) = chars.next_if(|c| !"+-=".contains(*c)) {
+                perms |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => return None,
+                };
+            }
+            let perms = perms & who;
+            match op {
+                '+' => bits |= perms,
+                '-' => bits &= !perms,
+                '=' => bits = bits & !who | perms,
+                _ => return None,
+            }
+        }
+    }
+    Some(bits)
+}
+
+#[cfg(unix)]
+fn parse_owner(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let os_val = primary_os_arg(name, parser)?;
+    let val = os_val.to_string_lossy();
+    let owner = if name == "-user" {
+        val.parse()
+            .ok()
+            .or_else(|| uzers::get_user_by_name(os_val).map(|user| user.uid()))
+            .map(Owner::User)
+            .ok_or_else(|| format!("`{}' is not the name of a known user", val))?
+    } else {
+        val.parse()
+            .ok()
+            .or_else(|| uzers::get_group_by_name(os_val).map(|group| group.gid()))
+            .map(Owner::Group)
+            .ok_or_else(|| format!("`{}' is not the name of an existing group", val))?
+    };
+    Ok(Box::new(owner))
+}
+
+#[cfg(not(unix))]
+fn parse_owner(name: &str, _parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    Err(format!("{} is not supported on this platform", name).into())
+}
+
+fn parse_depth(name: &str, parser: &mut Parser) -> MyResult<Box<dyn Predicate>> {
+    let val = primary_arg(name, parser)?;
+    let depth = val.parse().map_err(|_| {
+        format!(
+            "Expected a positive decimal integer argument to {}, but got `{}'",
+            name, val
+        )
+    })?;
+    if name == "-mindepth" {
+        parser.depth.min = Some(depth);
+    } else {
+        parser.depth.max = Some(depth);
+    }
+    Ok(Box::new(Always(true)))
+}
+
+// 括号里的表达式也可以当一个test用
+impl Predicate for Expr {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        Expr::matches(self, entry)
+    }
+}
+
+// --------------------------------------------------
+// A literal char for the regex. Non-ASCII is written as a hex escape so that
+// with Unicode off the chars of a byte-at-a-time glob stand for those bytes.
+fn escape_char(c: char) -> String {
+    match u32::from(c) {
+        0..=0x7F => regex::escape(c.encode_utf8(&mut [0; 1])),
+        n @ 0x80..=0xFF => format!("\\x{:02X}", n),
+        n => format!("\\x{{{:X}}}", n),
+    }
+}
+
+// Shell glob to an anchored regex: *, ? and [...] like fnmatch
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut re = String::from("^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            // 没有配对的]，[就是个普通字符
+            '[' => match glob_class(&chars[i + 1..]) {
+                Some((class, len)) => {
+                    re.push_str(&class);
+                    i += len;
+                }
+                None => re.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                re.push_str(&escape_char(chars[i]));
+            }
+            c => re.push_str(&escape_char(c)),
+        }
+        i += 1;
+    }
+    re.push('$');
+    re
+}
+
+const POSIX_CLASSES: [&str; 12] = [
+    "alnum", "alpha", "blank", "cntrl", "digit", "graph", "lower", "print", "punct", "space",
+    "upper", "xdigit",
+];
+
+// The bracket expression after a `[` as a regex class, with the number of
+// chars it took, or None when it never closes.
+fn glob_class(chars: &[char]) -> Option<(String, usize)> {
+    // 一个普通字符，可以用\转义
+    let literal = |i: usize| match chars.get(i)? {
+        '\\' => chars.get(i + 1).map(|&c| (c, 2)),
+        &c => Some((c, 1)),
+    };
+
+    let mut class = String::new();
+    let mut i = 0;
+    let negate = matches!(chars.first(), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+    let start = i;
+    loop {
+        match chars.get(i)? {
+            // 紧跟在[后面的]是普通字符
+            ']' if i > start => {
+                // 里面只有z-a这种空范围
+                let class = match (class.is_empty(), negate) {
+                    (true, false) => r"[^\s\S]".to_string(),
+                    (true, true) => r"[\s\S]".to_string(),
+                    (false, false) => format!("[{}]", class),
+                    (false, true) => format!("[^{}]", class),
+                };
+                return Some((class, i + 1));
+            }
+            '[' if chars.get(i + 1) == Some(&':') => {
+                let name: String = chars[i + 2..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_lowercase())
+                    .collect();
+                let end = i + 2 + name.len();
+                if POSIX_CLASSES.contains(&name.as_str())
+                    && chars.get(end..end + 2) == Some(&[':', ']'])
+                {
+                    class.push_str(&format!("[:{}:]", name));
+                    i = end + 2;
+                } else {
+                    class.push_str("\\[");
+                    i += 1;
+                }
+            }
+            _ => {
+                let (c, len) = literal(i)?;
+                i += len;
+                // a-z这样的范围，最后的-是普通字符
+                if chars.get(i) == Some(&'-') && chars.get(i + 1) != Some(&']') {
+                    let (end, len) = literal(i + 1)?;
+                    i += 1 + len;
+                    // 反过来的范围什么都不匹配
+                    if c <= end {
+                        class.push_str(&format!("{}-{}", escape_char(c), escape_char(end)));
+                    }
+                } else {
+                    // 每个字符都转义，免得&&、--、~~被regex当成集合运算
+                    class.push_str(&escape_char(c));
+                }
+            }
+        }
+    }
+}
+
+// --------------------------------------------------
+// Recursive descent, loosest first:
+//   or    = and { (-o | -or) and }
+//   and   = unary { [-a | -and] unary }
+//   unary = (! | -not) unary | ( or ) | primary
+fn parse_expression(args: &[OsString]) -> MyResult<(Expr, Depth)> {
+    let mut parser = Parser {
+        tokens: args.iter().peekable(),
+        depth: Depth::default(),
+    };
+    if parser.tokens.peek().is_none() {
+        return Ok((Expr::True, parser.depth));
+    }
+    let expr = parse_or(&mut parser)?;
+    match parser.tokens.next() {
+        None => Ok((expr, parser.depth)),
+        Some(tok) => {
+            Err(format!("invalid expression; unexpected `{}'", tok.to_string_lossy()).into())
+        }
+    }
+}
+
+fn parse_or(parser: &mut Parser) -> MyResult<Expr> {
+    let mut left = parse_and(parser)?;
+    while parser
+        .tokens
+        .next_if(|tok| *tok == "-o" || *tok == "-or")
+        .is_some()
+    {
+        left = left.or(parse_and(parser)?);
+    }
+    Ok(left)
+}
+
+fn parse_and(parser: &mut Parser) -> MyResult<Expr> {
+    let mut left = parse_unary(parser)?;
+    loop {
+        match parser
+            .tokens
+            .peek()
+            .map(|tok| tok.to_string_lossy())
+            .as_deref()
+        {
+            None | Some(")") | Some("-o") | Some("-or") => return Ok(left),
+            Some("-a") | Some("-and") => {
+                parser.tokens.next();
+            }
+            // 两个表达式挨着就是-a
+            _ => {}
+        }
+        left = Expr::And(Box::new(left), Box::new(parse_unary(parser)?));
+    }
+}
+
+fn parse_unary(parser: &mut Parser) -> MyResult<Expr> {
+    let tok = match parser.tokens.next() {
+        Some(tok) => tok.to_string_lossy(),
+        None => return Err("invalid expression; expected an expression at the end".into()),
+    };
+    match &*tok {
+        "!" | "-not" => Ok(Expr::Not(Box::new(parse_unary(parser)?))),
+        "(" => {
+            if parser.tokens.peek().is_some_and(|tok| *tok == ")") {
+                return Err("invalid expression; empty parentheses are not allowed".into());
+            }
+            let expr = parse_or(parser)?;
+            match parser.tokens.next() {
+                Some(tok) if tok == ")" => Ok(expr),
+                _ => Err("invalid expression; I was expecting to find a ')' somewhere but did not see one".into()),
+            }
+        }
+        ")" => Err("invalid expression; you have too many ')'".into()),
+        "-a" | "-and" | "-o" | "-or" => Err(format!(
+            "invalid expression; you have used a binary operator '{}' with nothing before it",
+            tok
+        )
+        .into()),
+        _ => match PRIMARIES.iter().find(|(name, _)| *name == tok) {
+            Some((name, parse)) => Ok(Expr::Test(parse(name, parser)?)),
+            None => Err(format!("unknown predicate `{}'", tok).into()),
+        },
+    }
+}
+
+pub fn run(config: Config) -> MyResult<()> {
+    let mut out = io::stdout().lock();
     for path in config.paths {
         // each directory entry is returned as a Result所有dir返回一个Result
-        let entries = WalkDir::new(path)
+        let mut walk = WalkDir::new(path);
+        if let Some(min) = config.depth.min {
+            walk = walk.min_depth(min);
+        }
+        if let Some(max) = config.depth.max {
+            walk = walk.max_depth(max);
+        }
+        let entries = walk
             // 返回的是一个Result<DirEntry>
             .into_iter()
             // 错误的输入STDERR，返回NONE，正确的通过进入下一个filter
@@ -136,13 +912,13 @@ pub fn run(config: Config) -> MyResult<()> {
                 }
                 Ok(entry) => Some(entry),
             })
-            .filter(type_filter)
-            .filter(name_filter)
-            .map(|entry| entry.path().display().to_string())
+            .filter(|entry| config.expr.matches(entry))
+            // 路径原样输出，不是UTF-8也不改
+            .map(|entry| entry.into_path().into_os_string().into_encoded_bytes())
             .collect::<Vec<_>>();
-        println!("{}", entries.join("\n"));
+        out.write_all(&entries.join(&b'\n'))?;
+        out.write_all(b"\n")?;
     }
 
     Ok(())
 }
-
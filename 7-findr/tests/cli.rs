@@ -35,11 +35,64 @@ fn skips_bad_dir() -> TestResult {
     Ok(())
 }
 
+// --------------------------------------------------
+// 不是UTF-8的参数不能让程序panic
+#[test]
+#[cfg(unix)]
+fn non_utf8_args() -> TestResult {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    let bad = OsStr::from_bytes(b"\xff");
+
+    Command::cargo_bin(PRG)?
+        .arg("tests/inputs/a")
+        .arg(bad)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("tests/inputs/a/a.txt"))
+        .stderr(predicate::str::contains("(os error 2)"));
+
+    Command::cargo_bin(PRG)?
+        .args([OsStr::new("tests/inputs"), OsStr::new("-name"), bad])
+        .assert()
+        .success()
+        .stdout("\n");
+    Ok(())
+}
+
+// 不是UTF-8的路径和文件名原样查找、匹配和输出
+#[test]
+#[cfg(unix)]
+fn non_utf8_paths() -> TestResult {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    let dir = tempfile::tempdir()?;
+    let start = dir.path().join(OsStr::from_bytes(b"d\xff"));
+    fs::create_dir(&start)?;
+    fs::write(start.join(OsStr::from_bytes(b"f\xfe.txt")), "")?;
+    fs::write(start.join("\u{e9}.txt"), "")?;
+    let start_bytes = start.as_os_str().as_bytes();
+    let path = |name: &[u8]| [start_bytes, b"/", name, b"\n"].concat();
+
+    let find = |glob: &OsStr, expected: Vec<u8>| -> TestResult {
+        Command::cargo_bin(PRG)?
+            .arg(&start)
+            .args([OsStr::new("-name"), glob])
+            .assert()
+            .success()
+            .stdout(expected);
+        Ok(())
+    };
+    // 文件名不是UTF-8的时候?是一个字节，是UTF-8的时候是一个字符
+    find(OsStr::new("f?.txt"), path(b"f\xfe.txt"))?;
+    find(OsStr::new("?.txt"), path("\u{e9}.txt".as_bytes()))?;
+    find(OsStr::from_bytes(b"*\xfe*"), path(b"f\xfe.txt"))?;
+    find(OsStr::new("*.csv"), b"\n".to_vec())
+}
+
 // --------------------------------------------------
 #[test]
 fn dies_bad_name() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&["--name", "*.csv"])
+        .args(["--name", "*.csv"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("Invalid --name \"*.csv\""));
@@ -51,7 +104,7 @@ fn dies_bad_name() -> TestResult {
 fn dies_bad_type() -> TestResult {
     let expected = "error: 'x' isn't a valid value for '--type <TYPE>...'";
     Command::cargo_bin(PRG)?
-        .args(&["--type", "x"])
+        .args(["--type", "x"])
         .assert()
         .failure()
         .stderr(predicate::str::contains(expected));
@@ -60,7 +113,7 @@ fn dies_bad_type() -> TestResult {
 
 // --------------------------------------------------
 #[cfg(windows)]
-fn format_file_name(expected_file: &str) -> Cow<str> {
+fn format_file_name(expected_file: &str) -> Cow<'_, str> {
     // 在这里是owned
     // Equivalent to: Cow::Owned(format!("{}.windows", expected_file))
     format!("{}.windows", expected_file).into()
@@ -68,7 +121,7 @@ fn format_file_name(expected_file: &str) -> Cow<str> {
 
 // --------------------------------------------------
 #[cfg(not(windows))]
-fn format_file_name(expected_file: &str) -> Cow<str> {
+fn format_file_name(expected_file: &str) -> Cow<'_, str> {
     // 在这里是借用
     // Equivalent to: Cow::Borrowed(expected_file)
     expected_file.into()
@@ -297,7 +350,7 @@ fn unreadable_dir() -> TestResult {
     //permissions.set_mode(0o000);
 
     std::process::Command::new("chmod")
-        .args(&["000", dirname])
+        .args(["000", dirname])
         .status()
         .expect("failed");
 
@@ -318,3 +371,374 @@ fn unreadable_dir() -> TestResult {
     assert!(stderr.contains("cant-touch-this: Permission denied"));
     Ok(())
 }
+
+// --------------------------------------------------
+#[test]
+fn expr_or() -> TestResult {
+    run(
+        &["tests/inputs", "-name", "*.csv", "-o", "-name", "*.mp3"],
+        "tests/expected/name_csv_mp3.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_not_parens() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "!",
+            "(",
+            "-type",
+            "d",
+            "-or",
+            "-name",
+            "*.csv",
+            ")",
+        ],
+        "tests/expected/expr_not_dir_csv.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_implicit_and() -> TestResult {
+    run(
+        &["tests/inputs", "-type", "f", "-name", "d*"],
+        "tests/expected/expr_type_f_name_d.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_and_options() -> TestResult {
+    run(
+        &["tests/inputs", "-t", "f", "-iname", "*.CSV"],
+        "tests/expected/expr_type_f_iname_csv.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_and_binds_tighter() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "-name",
+            "*.txt",
+            "-o",
+            "-name",
+            "*.tsv",
+            "-a",
+            "-type",
+            "l",
+        ],
+        "tests/expected/expr_precedence.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_regex() -> TestResult {
+    run(
+        &["tests/inputs", "-regex", r".*[/\\][a-c][.].*"],
+        "tests/expected/expr_regex.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_type_list_not() -> TestResult {
+    run(
+        &["tests/inputs", "-type", "f,l", "-not", "-name", "*.txt"],
+        "tests/expected/expr_type_fl_not_txt.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn expr_short_circuit() -> TestResult {
+    run(
+        &["tests/inputs", "-false", "-a", "-name", "*", "-o", "-true"],
+        "tests/expected/path1.txt",
+    )
+}
+
+// --------------------------------------------------
+fn dies_expr(args: &[&str], expected: &str) -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("tests/inputs")
+        .args(args)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(expected));
+    Ok(())
+}
+
+#[test]
+fn dies_expr_errors() -> TestResult {
+    dies_expr(
+        &["-o", "-true"],
+        "binary operator '-o' with nothing before it",
+    )?;
+    dies_expr(&["(", "-true"], "expecting to find a ')'")?;
+    dies_expr(&["-true", ")"], "unexpected `)'")?;
+    dies_expr(&["(", ")"], "empty parentheses")?;
+    dies_expr(&["!"], "expected an expression at the end")?;
+    dies_expr(&["-name"], "missing argument to `-name'")?;
+    dies_expr(&["-type", "f,x"], "Unknown argument to -type: x")?;
+    dies_expr(&["-true", "-foo", "1"], "unknown predicate `-foo'")
+}
+
+// --------------------------------------------------
+// 大小、时间、权限这些test用临时目录现造的文件
+const DAY: u64 = 24 * 60 * 60;
+
+fn make_tree() -> Result<tempfile::TempDir, Box<dyn std::error::Error>> {
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("sub/deep"))?;
+    fs::create_dir(root.join("empty_dir"))?;
+    fs::write(root.join("big"), vec![0; 3000])?;
+    fs::write(root.join("small"), "hi\n")?;
+    fs::write(root.join("zero"), "")?;
+    fs::write(root.join("sub/deep/x"), "x\n")?;
+
+    let ago = |days| SystemTime::now() - Duration::from_secs(days * DAY);
+    let times = fs::FileTimes::new()
+        .set_modified(ago(10))
+        .set_accessed(ago(10));
+    fs::File::options()
+        .write(true)
+        .open(root.join("big"))?
+        .set_times(times)?;
+    let times = fs::FileTimes::new()
+        .set_modified(ago(3))
+        .set_accessed(ago(20));
+    fs::File::options()
+        .write(true)
+        .open(root.join("small"))?
+        .set_times(times)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(
+            root.join("big"),
+            fs::Permissions::from_mode(0o755),
+        )?;
+        fs::set_permissions(
+            root.join("small"),
+            fs::Permissions::from_mode(0o640),
+        )?;
+        fs::set_permissions(
+            root.join("zero"),
+            fs::Permissions::from_mode(0o644),
+        )?;
+        fs::set_permissions(
+            root.join("sub/deep/x"),
+            fs::Permissions::from_mode(0o600),
+        )?;
+    }
+    Ok(dir)
+}
+
+// 输出里的路径去掉临时目录再比较，根目录自己是"."
+fn run_tree(args: &[&str], expected: &[&str]) -> TestResult {
+    run_in(&make_tree()?, args, expected)
+}
+
+fn run_in(
+    dir: &tempfile::TempDir,
+    args: &[&str],
+    expected: &[&str],
+) -> TestResult {
+    let root = dir.path().to_str().unwrap();
+    let cmd = Command::cargo_bin(PRG)?
+        .arg(root)
+        .args(args)
+        .assert()
+        .success();
+    let stdout = String::from_utf8(cmd.get_output().stdout.clone())?;
+    let mut lines: Vec<String> = stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let rel = line
+                .strip_prefix(root)
+                .unwrap()
+                .trim_start_matches(['/', '\\']);
+            if rel.is_empty() {
+                ".".to_string()
+            } else {
+                rel.replace('\\', "/")
+            }
+        })
+        .collect();
+    lines.sort();
+
+    let mut expected = expected.to_vec();
+    expected.sort();
+    assert_eq!(lines, expected);
+    Ok(())
+}
+
+#[test]
+fn size() -> TestResult {
+    run_tree(&["-type", "f", "-size", "+2k"], &["big"])?;
+    run_tree(&["-type", "f", "-size", "-1"], &["zero"])?;
+    run_tree(&["-type", "f", "-size", "3c"], &["small"])?;
+    run_tree(&["-type", "f", "-size", "6"], &["big"])
+}
+
+#[test]
+fn mtime() -> TestResult {
+    run_tree(&["-mtime", "+5"], &["big"])?;
+    run_tree(&["-type", "f", "-mtime", "3"], &["small"])?;
+    run_tree(&["-type", "f", "-mtime", "-1"], &["zero", "sub/deep/x"])
+}
+
+#[test]
+fn atime() -> TestResult {
+    run_tree(&["-atime", "+15"], &["small"])
+}
+
+#[test]
+fn ctime() -> TestResult {
+    run_tree(&["-ctime", "+0"], &[])
+}
+
+#[test]
+fn newer() -> TestResult {
+    let dir = make_tree()?;
+    let small = dir.path().join("small");
+    run_in(
+        &dir,
+        &["-type", "f", "-newer", small.to_str().unwrap()],
+        &["zero", "sub/deep/x"],
+    )
+}
+
+#[test]
+#[cfg(unix)]
+fn perm() -> TestResult {
+    run_tree(&["-perm", "640"], &["small"])?;
+    run_tree(&["-type", "f", "-perm", "-u+x"], &["big"])?;
+    run_tree(&["-type", "f", "-perm", "/g=w,o=r"], &["big", "zero"])?;
+    run_tree(&["-perm", "u=rw,go=r"], &["zero"])
+}
+
+#[test]
+#[cfg(unix)]
+fn user_and_group() -> TestResult {
+    use std::os::unix::fs::MetadataExt;
+    let meta = fs::metadata(".")?;
+    let everything = [
+        ".",
+        "big",
+        "empty_dir",
+        "small",
+        "sub",
+        "sub/deep",
+        "sub/deep/x",
+        "zero",
+    ];
+    run_tree(&["-user", &meta.uid().to_string()], &everything)?;
+    run_tree(&["-group", &meta.gid().to_string()], &everything)?;
+    run_tree(&["!", "-user", &meta.uid().to_string()], &[])
+}
+
+#[test]
+fn empty() -> TestResult {
+    run_tree(&["-empty"], &["empty_dir", "zero"])
+}
+
+#[test]
+fn depth() -> TestResult {
+    run_tree(
+        &["-maxdepth", "1", "-type", "d"],
+        &[".", "empty_dir", "sub"],
+    )?;
+    run_tree(&["-type", "d", "-mindepth", "2"], &["sub/deep"])?;
+    run_tree(
+        &["-mindepth", "1", "-maxdepth", "1", "-name", "s*"],
+        &["small", "sub"],
+    )
+}
+
+#[test]
+fn stale_large_files() -> TestResult {
+    run_tree(
+        &["-type", "f", "(", "-size", "+1k", "-mtime", "+7", ")"],
+        &["big"],
+    )
+}
+
+#[test]
+fn dies_bad_tests() -> TestResult {
+    dies_expr(&["-size", "1x"], "invalid -size type `x'")?;
+    dies_expr(&["-mtime", "x"], "invalid argument `x' to `-mtime'")?;
+    dies_expr(&["-perm", "8"], "invalid mode `8'")?;
+    dies_expr(&["-maxdepth", "-1"], "Expected a positive decimal integer")?;
+    dies_expr(&["-newer", &gen_bad_file()], "os error 2")
+}
+
+#[test]
+#[cfg(unix)]
+fn dies_unknown_user() -> TestResult {
+    dies_expr(
+        &["-user", "no-such-user-x"],
+        "is not the name of a known user",
+    )?;
+    dies_expr(
+        &["-group", "no-such-group-x"],
+        "is not the name of an existing group",
+    )
+}
+
+// --------------------------------------------------
+// 方括号按fnmatch的规矩来，不能漏成regex的语法
+#[test]
+fn name_brackets() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    for name in ["[abc", "a", "b", "1x", "22", "&", "~", "]"] {
+        fs::write(dir.path().join(name), "")?;
+    }
+    let name =
+        |glob, expected| run_in(&dir, &["-type", "f", "-name", glob], expected);
+    name("[abc", &["[abc"])?;
+    name("[[:digit:]]*", &["1x", "22"])?;
+    name("[[:alpha:][:digit:]]", &["a", "b"])?;
+    name("[a&&b]", &["&", "a", "b"])?;
+    name("[~~]", &["~"])?;
+    name("[]]", &["]"])?;
+    name("[!a-z]*", &["1x", "22", "&", "[abc", "]", "~"])?;
+    name("[z-a]", &[])?;
+    run_in(&dir, &["-type", "f", "-iname", "[A-B]"], &["a", "b"])
+}
+
+// --------------------------------------------------
+// 文件名里可以有换行，*、?和-regex的.都要能匹配
+#[test]
+#[cfg(unix)]
+fn newline_in_name() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("a\nb.txt"), "")?;
+    let root = dir.path().to_str().unwrap();
+    let expected = format!("{}/a\nb.txt\n", root);
+    for args in [
+        ["-name", "*.txt"],
+        ["-name", "a?b.txt"],
+        ["-regex", ".*/a.b\\.txt"],
+    ] {
+        Command::cargo_bin(PRG)?
+            .arg(root)
+            .args(args)
+            .assert()
+            .success()
+            .stdout(expected.clone());
+    }
+    Ok(())
+}
@@ -111,23 +111,19 @@ pub fn get_args() -> MyResult<Config> {
 fn parse_index(input: &str) -> Result<usize, String> {
     // 搞个closure
     let value_error = || format!("illegal list value: \"{}\"", input);
-    input
-        // starts_with, str功能，如果从+开始
-        .starts_with("+")
-        // 那么就报错
-        .then(|| Err(value_error()))
-        // 解包或
-        .unwrap_or_else(|| {
-            // 不然
-            input
-                // parse成非0 usize, positive integer value
-                .parse::<NonZeroUsize>()
-                // 当input vlaue parses 成功，cast the value to a usize, 然后-1
-                // 为了对齐0 index, 用户输入1其实是第0位
-                .map(|n| usize::from(n) - 1)
-                // 如果失败，输出错误信息
-                .map_err(|_| value_error())
-        })
+    // starts_with, str功能，如果从+开始, 那么就报错
+    if input.starts_with('+') {
+        Err(value_error())
+    } else {
+        input
+            // parse成非0 usize, positive integer value
+            .parse::<NonZeroUsize>()
+            // 当input vlaue parses 成功，cast the value to a usize, 然后-1
+            // 为了对齐0 index, 用户输入1其实是第0位
+            .map(|n| usize::from(n) - 1)
+            // 如果失败，输出错误信息
+            .map_err(|_| value_error())
+    }
 }
 
 fn parse_pos(range: &str) -> MyResult<PositionList> {
@@ -138,7 +134,6 @@ fn parse_pos(range: &str) -> MyResult<PositionList> {
     range
         // 用comma分开
         .split(',')
-        .into_iter()
         .map(|val| {
             // 如果parse_index成功解析出来single的interger, 就是固定某一column的cut
             // 则cut n..n+1列的内容(其实就是处理n自己)
@@ -265,43 +260,52 @@ fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
 }
 
 
-pub fn run(config: Config) -> MyResult<()> {
+// 返回没能cut的文件数
+pub fn run(config: Config) -> MyResult<usize> {
     // println!("{:#?}", &config);
+    let mut num_failed = 0;
     for filename in &config.files {
-        match open(filename) {
-            Err(err) => eprintln!("{}: {}", filename, err),
-            Ok(file) => match &config.extract {
-                Fields(field_pos) => {
-                    let mut reader = ReaderBuilder::new()
-                        .delimiter(config.delimiter)
-                        // 不把第一行当头处理，不然还要额外处理头
-                        .has_headers(false)
-                        .from_reader(file);
-
-                    // 为了在输出中也正确转义分隔符，需要一个writer
-                    let mut wtr = WriterBuilder::new()
-                        .delimiter(config.delimiter)
-                        .from_writer(io::stdout());
-
-                    for record in reader.records() {
-                        let record = record?;
-                        // 写入record
-                        wtr.write_record(extract_fields(
-                                &record, field_pos,
-                        ))?;
-                    }
-                }
-                Bytes(byte_pos) => {
-                    for line in file.lines() {
-                        println!("{}", extract_bytes(&line?, byte_pos));
-                    }
-                }
-                Chars(char_pos) => {
-                    for line in file.lines() {
-                        println!("{}", extract_chars(&line?, char_pos));
-                    }
-                }
-            },
+        // 打开和读取的错误都报出来，接着处理下一个文件
+        if let Err(err) = open(filename).and_then(|file| cut(file, &config)) {
+            eprintln!("{}: {}", filename, err);
+            num_failed += 1;
+        }
+    }
+
+    Ok(num_failed)
+}
+
+fn cut(file: impl BufRead, config: &Config) -> MyResult<()> {
+    match &config.extract {
+        Fields(field_pos) => {
+            let mut reader = ReaderBuilder::new()
+                .delimiter(config.delimiter)
+                // 不把第一行当头处理，不然还要额外处理头
+                .has_headers(false)
+                .from_reader(file);
+
+            // 为了在输出中也正确转义分隔符，需要一个writer
+            let mut wtr = WriterBuilder::new()
+                .delimiter(config.delimiter)
+                .from_writer(io::stdout());
+
+            for record in reader.records() {
+                let record = record?;
+                // 写入record
+                wtr.write_record(extract_fields(
+                        &record, field_pos,
+                ))?;
+            }
+        }
+        Bytes(byte_pos) => {
+            for line in file.lines() {
+                println!("{}", extract_bytes(&line?, byte_pos));
+            }
+        }
+        Chars(char_pos) => {
+            for line in file.lines() {
+                println!("{}", extract_chars(&line?, char_pos));
+            }
         }
     }
     Ok(())
@@ -309,6 +313,8 @@ pub fn run(config: Config) -> MyResult<()> {
 
 
 #[cfg(test)]
+// 测试里的&[0..1]就是想要只有一个范围的slice
+#[allow(clippy::single_range_in_vec_init)]
 mod unit_tests {
     use super::{extract_bytes, extract_chars, extract_fields, parse_pos};
     use csv::StringRecord;
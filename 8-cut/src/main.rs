@@ -1,6 +1,10 @@
 fn main() {
-    if let Err(e) = cutr::get_args().and_then(cutr::run) {
-        eprintln!("{}", e);
-        std::process::exit(1);
+    match cutr::get_args().and_then(cutr::run) {
+        Ok(0) => {}
+        Ok(_) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }